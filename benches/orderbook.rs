@@ -0,0 +1,63 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rust_decimal::Decimal;
+use trading_engine::matching_engine::orderbook::{BidOrAsk, Order, OrderBook};
+
+const LEVEL_COUNTS: [u32; 3] = [1_000, 10_000, 50_000];
+
+// Bids at 1..=levels, asks at levels+1..=2*levels, one order of size 1 per level
+fn build_book(levels: u32) -> OrderBook {
+    let mut order_book = OrderBook::new();
+    for i in 1..=levels {
+        order_book.add_limit_order(Decimal::from(i), Order::new(BidOrAsk::Bid, Decimal::ONE));
+        order_book.add_limit_order(Decimal::from(levels + i), Order::new(BidOrAsk::Ask, Decimal::ONE));
+    }
+    order_book
+}
+
+fn bench_add_limit_order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("add_limit_order");
+    for levels in LEVEL_COUNTS {
+        let order_book = build_book(levels);
+        group.bench_with_input(BenchmarkId::from_parameter(levels), &levels, |b, &levels| {
+            b.iter_batched_ref(
+                || order_book.clone(),
+                |book| {
+                    // A new level in the middle of the bid side and a join on an existing level
+                    book.add_limit_order(Decimal::new(i64::from(levels), 0) / Decimal::TWO + Decimal::new(5, 1), Order::new(BidOrAsk::Bid, Decimal::ONE));
+                    book.add_limit_order(Decimal::from(levels), Order::new(BidOrAsk::Bid, Decimal::ONE));
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_fill_market_order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill_market_order");
+    for levels in LEVEL_COUNTS {
+        let order_book = build_book(levels);
+        group.bench_with_input(BenchmarkId::from_parameter(levels), &levels, |b, _| {
+            b.iter_batched_ref(
+                || order_book.clone(),
+                |book| {
+                    // Sweeps ten levels of the ask side
+                    let mut market_order = Order::new(BidOrAsk::Bid, Decimal::TEN);
+                    black_box(book.fill_market_order(&mut market_order));
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_best_prices(c: &mut Criterion) {
+    let order_book = build_book(10_000);
+    c.bench_function("best_bid_ask/10000", |b| {
+        b.iter(|| black_box((order_book.best_bid(), order_book.best_ask())))
+    });
+}
+
+criterion_group!(benches, bench_add_limit_order, bench_fill_market_order, bench_best_prices);
+criterion_main!(benches);
@@ -4,13 +4,44 @@
 CREATE TABLE IF NOT EXISTS users (
     user_id SERIAL PRIMARY KEY,
     username VARCHAR(50) UNIQUE NOT NULL,
-    cash_balance DECIMAL(18, 8) DEFAULT 0,
     realized_pnl DECIMAL(18, 8) DEFAULT 0,
     unrealized_pnl DECIMAL(18, 8) DEFAULT 0,
     created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
 );
 
+-- What each user holds of each asset: `available` can be spent or withdrawn, `locked` is
+-- held by open orders (the quote asset for buys, the base asset for sells)
+CREATE TABLE IF NOT EXISTS balances (
+    user_id INTEGER REFERENCES users(user_id) ON DELETE CASCADE,
+    asset VARCHAR(10) NOT NULL,
+    available DECIMAL(18, 8) NOT NULL DEFAULT 0,
+    locked DECIMAL(18, 8) NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, asset)
+);
+
+-- Double-entry ledger of every balance movement. Entries are only ever inserted, and the
+-- postings of an entry sum to zero in each asset. A user's postings add up to their
+-- balance (available plus locked); the other accounts are the exchange's side.
+CREATE TABLE IF NOT EXISTS ledger_entries (
+    entry_id SERIAL PRIMARY KEY,
+    kind VARCHAR(12) CHECK (kind IN ('deposit', 'withdrawal', 'trade', 'fee', 'adjustment')) NOT NULL,
+    reference_id INTEGER, -- The trade, for trades and fees
+    description VARCHAR(200),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS ledger_postings (
+    posting_id SERIAL PRIMARY KEY,
+    entry_id INTEGER REFERENCES ledger_entries(entry_id) NOT NULL,
+    account VARCHAR(12) CHECK (account IN ('user', 'external', 'fees', 'adjustments')) NOT NULL,
+    user_id INTEGER REFERENCES users(user_id),
+    asset VARCHAR(10) NOT NULL,
+    amount DECIMAL(18, 8) NOT NULL,
+    CHECK ((account = 'user') = (user_id IS NOT NULL))
+);
+
 -- Positions table
 CREATE TABLE IF NOT EXISTS positions (
     position_id SERIAL PRIMARY KEY,
@@ -22,18 +53,36 @@ CREATE TABLE IF NOT EXISTS positions (
     UNIQUE(user_id, symbol)
 );
 
+-- Tradable symbols and the constraints orders for them must meet. Orders for symbols
+-- not listed here are refused, as are new orders while a market is halted.
+CREATE TABLE IF NOT EXISTS instruments (
+    symbol VARCHAR(20) PRIMARY KEY,
+    tick_size DECIMAL(18, 8) NOT NULL CHECK (tick_size > 0),
+    lot_size DECIMAL(18, 8) NOT NULL CHECK (lot_size > 0),
+    min_quantity DECIMAL(18, 8) NOT NULL DEFAULT 0,
+    max_quantity DECIMAL(18, 8),
+    min_notional DECIMAL(18, 8) NOT NULL DEFAULT 0,
+    price_precision INTEGER NOT NULL CHECK (price_precision BETWEEN 0 AND 8),
+    status VARCHAR(10) CHECK (status IN ('active', 'halted')) NOT NULL DEFAULT 'active',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
 -- Orders table (enhanced)
 CREATE TABLE IF NOT EXISTS orders (
     order_id SERIAL PRIMARY KEY,
     user_id INTEGER REFERENCES users(user_id) ON DELETE CASCADE,
     symbol VARCHAR(20) NOT NULL,
     side VARCHAR(4) CHECK (side IN ('buy', 'sell')) NOT NULL,
-    order_type VARCHAR(10) CHECK (order_type IN ('limit', 'market', 'stop')) NOT NULL,
+    order_type VARCHAR(10) CHECK (order_type IN ('limit', 'market', 'stop', 'stop_limit')) NOT NULL,
     quantity DECIMAL(18, 8) NOT NULL,
     limit_price DECIMAL(18, 8),
+    stop_price DECIMAL(18, 8),
     filled_quantity DECIMAL(18, 8) DEFAULT 0,
     remaining_quantity DECIMAL(18, 8) NOT NULL,
     status VARCHAR(10) CHECK (status IN ('pending', 'active', 'filled', 'cancelled', 'rejected')) DEFAULT 'pending',
+    reject_reason VARCHAR(30),
+    locked_amount DECIMAL(18, 8) NOT NULL DEFAULT 0, -- Still locked in the balance of the asset the order pays with
     time_in_force VARCHAR(10) CHECK (time_in_force IN ('GTC', 'IOC', 'FOK', 'DAY')) DEFAULT 'GTC',
     submission_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
@@ -53,6 +102,30 @@ CREATE TABLE IF NOT EXISTS trades (
     timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP
 );
 
+-- Maker/taker fee tiers. A user pays the rates of the highest tier their trailing 30-day
+-- notional in the symbol reaches; symbol '*' holds the tiers of symbols without their own.
+CREATE TABLE IF NOT EXISTS fee_tiers (
+    tier_id SERIAL PRIMARY KEY,
+    symbol VARCHAR(20) NOT NULL DEFAULT '*',
+    min_volume DECIMAL(18, 8) NOT NULL DEFAULT 0,
+    maker_rate DECIMAL(10, 8) NOT NULL,
+    taker_rate DECIMAL(10, 8) NOT NULL,
+    UNIQUE(symbol, min_volume)
+);
+
+-- Fees charged on each side of a trade
+CREATE TABLE IF NOT EXISTS trade_fees (
+    fee_id SERIAL PRIMARY KEY,
+    trade_id INTEGER REFERENCES trades(trade_id) NOT NULL,
+    user_id INTEGER REFERENCES users(user_id) NOT NULL,
+    order_id INTEGER REFERENCES orders(order_id) NOT NULL,
+    liquidity VARCHAR(5) CHECK (liquidity IN ('maker', 'taker')) NOT NULL,
+    rate DECIMAL(10, 8) NOT NULL,
+    fee DECIMAL(18, 8) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(trade_id, liquidity)
+);
+
 -- Order book entries table (for maintaining order book state)
 CREATE TABLE IF NOT EXISTS order_book_entries (
     id SERIAL PRIMARY KEY,
@@ -84,12 +157,45 @@ CREATE INDEX IF NOT EXISTS idx_trades_symbol ON trades(symbol);
 CREATE INDEX IF NOT EXISTS idx_trades_timestamp ON trades(timestamp);
 CREATE INDEX IF NOT EXISTS idx_positions_user_id ON positions(user_id);
 CREATE INDEX IF NOT EXISTS idx_order_book_symbol_side ON order_book_entries(symbol, side);
+CREATE INDEX IF NOT EXISTS idx_trade_fees_user_id ON trade_fees(user_id);
+CREATE INDEX IF NOT EXISTS idx_ledger_postings_user_asset ON ledger_postings(user_id, asset);
+CREATE INDEX IF NOT EXISTS idx_ledger_postings_entry_id ON ledger_postings(entry_id);
 
 -- Insert sample users
-INSERT INTO users (username, cash_balance) VALUES ('trader1', 100000.00) ON CONFLICT DO NOTHING;
-INSERT INTO users (username, cash_balance) VALUES ('trader2', 50000.00) ON CONFLICT DO NOTHING;
+INSERT INTO users (username) VALUES ('trader1') ON CONFLICT DO NOTHING;
+INSERT INTO users (username) VALUES ('trader2') ON CONFLICT DO NOTHING;
+INSERT INTO balances (user_id, asset, available) SELECT user_id, 'USD', 100000.00 FROM users WHERE username = 'trader1' ON CONFLICT DO NOTHING;
+INSERT INTO balances (user_id, asset, available) SELECT user_id, 'USD', 50000.00 FROM users WHERE username = 'trader2' ON CONFLICT DO NOTHING;
+
+-- The sample balances are deposits as far as the ledger is concerned
+WITH entry AS (
+    INSERT INTO ledger_entries (kind, description) SELECT 'deposit', 'Opening balance'
+    WHERE NOT EXISTS (SELECT 1 FROM ledger_postings p JOIN users u ON u.user_id = p.user_id WHERE u.username = 'trader1')
+    RETURNING entry_id
+)
+INSERT INTO ledger_postings (entry_id, account, user_id, asset, amount)
+SELECT entry_id, 'user', user_id, 'USD', 100000.00 FROM entry, users WHERE username = 'trader1'
+UNION ALL SELECT entry_id, 'external', NULL, 'USD', -100000.00 FROM entry;
+WITH entry AS (
+    INSERT INTO ledger_entries (kind, description) SELECT 'deposit', 'Opening balance'
+    WHERE NOT EXISTS (SELECT 1 FROM ledger_postings p JOIN users u ON u.user_id = p.user_id WHERE u.username = 'trader2')
+    RETURNING entry_id
+)
+INSERT INTO ledger_postings (entry_id, account, user_id, asset, amount)
+SELECT entry_id, 'user', user_id, 'USD', 50000.00 FROM entry, users WHERE username = 'trader2'
+UNION ALL SELECT entry_id, 'external', NULL, 'USD', -50000.00 FROM entry;
+
+-- Insert sample instruments
+INSERT INTO instruments (symbol, tick_size, lot_size, min_quantity, max_quantity, min_notional, price_precision) VALUES ('BTC/USD', 0.01, 0.0001, 0.0001, 100, 10, 2) ON CONFLICT DO NOTHING;
+INSERT INTO instruments (symbol, tick_size, lot_size, min_quantity, max_quantity, min_notional, price_precision) VALUES ('ETH/USD', 0.01, 0.001, 0.001, 1000, 10, 2) ON CONFLICT DO NOTHING;
+INSERT INTO instruments (symbol, tick_size, lot_size, min_quantity, max_quantity, min_notional, price_precision) VALUES ('AAPL', 0.01, 1, 1, 10000, 1, 2) ON CONFLICT DO NOTHING;
+
+-- Insert default fee tiers
+INSERT INTO fee_tiers (symbol, min_volume, maker_rate, taker_rate) VALUES ('*', 0, 0.001, 0.002) ON CONFLICT DO NOTHING;
+INSERT INTO fee_tiers (symbol, min_volume, maker_rate, taker_rate) VALUES ('*', 100000, 0.0008, 0.0016) ON CONFLICT DO NOTHING;
+INSERT INTO fee_tiers (symbol, min_volume, maker_rate, taker_rate) VALUES ('*', 1000000, 0.0005, 0.001) ON CONFLICT DO NOTHING;
 
 -- Insert sample market data
-INSERT INTO market_data (symbol) VALUES ('BTC') ON CONFLICT DO NOTHING;
-INSERT INTO market_data (symbol) VALUES ('ETH') ON CONFLICT DO NOTHING;
+INSERT INTO market_data (symbol) VALUES ('BTC/USD') ON CONFLICT DO NOTHING;
+INSERT INTO market_data (symbol) VALUES ('ETH/USD') ON CONFLICT DO NOTHING;
 INSERT INTO market_data (symbol) VALUES ('AAPL') ON CONFLICT DO NOTHING;
\ No newline at end of file
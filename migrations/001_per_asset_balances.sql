@@ -0,0 +1,60 @@
+-- Moves a database created before balances were held per asset onto them. Each user's
+-- `cash_balance` becomes their available USD balance, recorded in the ledger as an opening
+-- deposit so it reconciles, and orders gain the columns accepting and rejecting them needs.
+-- Run it once, before `init.sql` creates the tables added since:
+--
+--   psql "$DATABASE_URL" -f migrations/001_per_asset_balances.sql -f init.sql
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS balances (
+    user_id INTEGER REFERENCES users(user_id) ON DELETE CASCADE,
+    asset VARCHAR(10) NOT NULL,
+    available DECIMAL(18, 8) NOT NULL DEFAULT 0,
+    locked DECIMAL(18, 8) NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, asset)
+);
+
+CREATE TABLE IF NOT EXISTS ledger_entries (
+    entry_id SERIAL PRIMARY KEY,
+    kind VARCHAR(12) CHECK (kind IN ('deposit', 'withdrawal', 'trade', 'fee', 'adjustment')) NOT NULL,
+    reference_id INTEGER, -- The trade, for trades and fees
+    description VARCHAR(200),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS ledger_postings (
+    posting_id SERIAL PRIMARY KEY,
+    entry_id INTEGER REFERENCES ledger_entries(entry_id) NOT NULL,
+    account VARCHAR(12) CHECK (account IN ('user', 'external', 'fees', 'adjustments')) NOT NULL,
+    user_id INTEGER REFERENCES users(user_id),
+    asset VARCHAR(10) NOT NULL,
+    amount DECIMAL(18, 8) NOT NULL,
+    CHECK ((account = 'user') = (user_id IS NOT NULL))
+);
+
+INSERT INTO balances (user_id, asset, available)
+SELECT user_id, 'USD', cash_balance FROM users WHERE cash_balance <> 0;
+
+-- One entry for every opening balance, against the external account
+WITH entry AS (
+    INSERT INTO ledger_entries (kind, description) SELECT 'deposit', 'Opening balance'
+    WHERE EXISTS (SELECT 1 FROM users WHERE cash_balance <> 0)
+    RETURNING entry_id
+)
+INSERT INTO ledger_postings (entry_id, account, user_id, asset, amount)
+SELECT entry_id, 'user', user_id, 'USD', cash_balance FROM entry, users WHERE cash_balance <> 0
+UNION ALL SELECT entry_id, 'external', NULL, 'USD', -SUM(cash_balance) FROM entry, users GROUP BY entry_id;
+
+ALTER TABLE users DROP COLUMN cash_balance;
+
+-- Orders placed before this locked nothing, so their fills are paid out of available
+ALTER TABLE orders
+    DROP CONSTRAINT IF EXISTS orders_order_type_check,
+    ADD CONSTRAINT orders_order_type_check CHECK (order_type IN ('limit', 'market', 'stop', 'stop_limit')),
+    ADD COLUMN IF NOT EXISTS stop_price DECIMAL(18, 8),
+    ADD COLUMN IF NOT EXISTS reject_reason VARCHAR(30),
+    ADD COLUMN IF NOT EXISTS locked_amount DECIMAL(18, 8) NOT NULL DEFAULT 0;
+
+COMMIT;
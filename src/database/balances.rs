@@ -0,0 +1,86 @@
+use rust_decimal::Decimal;
+use tokio_postgres::{Error, Row};
+use super::{utc_timestamp, GenericClient};
+use crate::models::Balance;
+
+// The columns a `Balance` is read from
+pub const BALANCE_COLUMNS: &str = "user_id, asset, available, locked, updated_at";
+
+impl TryFrom<&Row> for Balance {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(Balance {
+            user_id: row.try_get("user_id")?,
+            asset: row.try_get("asset")?,
+            available: row.try_get("available")?,
+            locked: row.try_get("locked")?,
+            updated_at: utc_timestamp(row, "updated_at")?,
+        })
+    }
+}
+
+pub async fn for_user(client: &impl GenericClient, user_id: i32) -> Result<Vec<Balance>, Error> {
+    let rows = client
+        .query(
+            &format!("SELECT {} FROM balances WHERE user_id = $1 ORDER BY asset", BALANCE_COLUMNS),
+            &[&user_id],
+        )
+        .await?;
+    rows.iter().map(Balance::try_from).collect()
+}
+
+// The available balances the user holds of the given assets, locked until the end of the
+// transaction so no other order can spend them in between
+pub async fn available_for_update(
+    client: &impl GenericClient,
+    user_id: i32,
+    assets: &[&str],
+) -> Result<Vec<(String, Decimal)>, Error> {
+    let rows = client
+        .query(
+            "SELECT asset, available FROM balances WHERE user_id = $1 AND asset = ANY($2) FOR UPDATE",
+            &[&user_id, &assets],
+        )
+        .await?;
+    rows.iter().map(|row| Ok((row.try_get("asset")?, row.try_get("available")?))).collect()
+}
+
+// Adds to the available and locked amounts of an asset; either can be negative
+pub async fn adjust(
+    client: &impl GenericClient,
+    user_id: i32,
+    asset: &str,
+    available: Decimal,
+    locked: Decimal,
+) -> Result<Balance, Error> {
+    let row = client
+        .query_one(
+            &format!(
+                "INSERT INTO balances (user_id, asset, available, locked) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (user_id, asset) DO UPDATE SET available = balances.available + EXCLUDED.available,
+                 locked = balances.locked + EXCLUDED.locked, updated_at = CURRENT_TIMESTAMP
+                 RETURNING {}",
+                BALANCE_COLUMNS
+            ),
+            &[&user_id, &asset, &available, &locked],
+        )
+        .await?;
+    Balance::try_from(&row)
+}
+
+// None when the available balance does not cover the amount
+pub async fn withdraw(client: &impl GenericClient, user_id: i32, asset: &str, amount: Decimal) -> Result<Option<Balance>, Error> {
+    let row = client
+        .query_opt(
+            &format!(
+                "UPDATE balances SET available = available - $3, updated_at = CURRENT_TIMESTAMP
+                 WHERE user_id = $1 AND asset = $2 AND available >= $3
+                 RETURNING {}",
+                BALANCE_COLUMNS
+            ),
+            &[&user_id, &asset, &amount],
+        )
+        .await?;
+    row.as_ref().map(Balance::try_from).transpose()
+}
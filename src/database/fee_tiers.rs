@@ -0,0 +1,27 @@
+use tokio_postgres::{Error, Row};
+use super::GenericClient;
+use crate::models::{FeeTier, InstrumentId};
+
+impl TryFrom<&Row> for FeeTier {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(FeeTier {
+            min_volume: row.try_get("min_volume")?,
+            maker_rate: row.try_get("maker_rate")?,
+            taker_rate: row.try_get("taker_rate")?,
+        })
+    }
+}
+
+// The symbol's own tiers, or those of `default_symbol` when it has none
+pub async fn for_symbol(client: &impl GenericClient, symbol: &InstrumentId, default_symbol: &str) -> Result<Vec<FeeTier>, Error> {
+    let rows = client
+        .query(
+            "SELECT min_volume, maker_rate, taker_rate FROM fee_tiers
+             WHERE symbol = COALESCE((SELECT symbol FROM fee_tiers WHERE symbol = $1 LIMIT 1), $2)",
+            &[&symbol, &default_symbol],
+        )
+        .await?;
+    rows.iter().map(FeeTier::try_from).collect()
+}
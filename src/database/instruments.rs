@@ -0,0 +1,75 @@
+use tokio_postgres::{Error, Row};
+use super::{utc_timestamp, GenericClient};
+use crate::models::{CreateInstrumentRequest, Instrument, InstrumentId, MarketStatus};
+
+// The columns an `Instrument` is read from
+pub const INSTRUMENT_COLUMNS: &str =
+    "symbol, tick_size, lot_size, min_quantity, max_quantity, min_notional, price_precision, status, created_at, updated_at";
+
+impl TryFrom<&Row> for Instrument {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(Instrument {
+            symbol: row.try_get("symbol")?,
+            tick_size: row.try_get("tick_size")?,
+            lot_size: row.try_get("lot_size")?,
+            min_quantity: row.try_get("min_quantity")?,
+            max_quantity: row.try_get("max_quantity")?,
+            min_notional: row.try_get("min_notional")?,
+            price_precision: row.try_get::<_, i32>("price_precision")? as u32,
+            status: row.try_get("status")?,
+            created_at: utc_timestamp(row, "created_at")?,
+            updated_at: utc_timestamp(row, "updated_at")?,
+        })
+    }
+}
+
+pub async fn all(client: &impl GenericClient) -> Result<Vec<Instrument>, Error> {
+    let rows = client
+        .query(&format!("SELECT {} FROM instruments", INSTRUMENT_COLUMNS), &[])
+        .await?;
+    rows.iter().map(Instrument::try_from).collect()
+}
+
+// None if the symbol is already registered
+pub async fn insert(
+    client: &impl GenericClient,
+    request: &CreateInstrumentRequest,
+    price_precision: u32,
+) -> Result<Option<Instrument>, Error> {
+    let row = client
+        .query_opt(
+            &format!(
+                "INSERT INTO instruments (symbol, tick_size, lot_size, min_quantity, max_quantity, min_notional, price_precision)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (symbol) DO NOTHING
+                 RETURNING {}",
+                INSTRUMENT_COLUMNS
+            ),
+            &[
+                &request.symbol,
+                &request.tick_size,
+                &request.lot_size,
+                &request.min_quantity.unwrap_or_default(),
+                &request.max_quantity,
+                &request.min_notional.unwrap_or_default(),
+                &(price_precision as i32),
+            ],
+        )
+        .await?;
+    row.as_ref().map(Instrument::try_from).transpose()
+}
+
+// None if the symbol is not registered
+pub async fn set_status(client: &impl GenericClient, symbol: &InstrumentId, status: MarketStatus) -> Result<Option<Instrument>, Error> {
+    let row = client
+        .query_opt(
+            &format!(
+                "UPDATE instruments SET status = $1, updated_at = CURRENT_TIMESTAMP WHERE symbol = $2 RETURNING {}",
+                INSTRUMENT_COLUMNS
+            ),
+            &[&status, &symbol],
+        )
+        .await?;
+    row.as_ref().map(Instrument::try_from).transpose()
+}
@@ -0,0 +1,113 @@
+use rust_decimal::Decimal;
+use tokio_postgres::{Error, Row};
+use super::{utc_timestamp, GenericClient};
+use crate::models::{BalanceDrift, LedgerEntryKind, StatementLine};
+
+impl TryFrom<&Row> for StatementLine {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(StatementLine {
+            entry_id: row.try_get("entry_id")?,
+            kind: row.try_get("kind")?,
+            reference_id: row.try_get("reference_id")?,
+            description: row.try_get("description")?,
+            asset: row.try_get("asset")?,
+            amount: row.try_get("amount")?,
+            balance: row.try_get("balance")?,
+            created_at: utc_timestamp(row, "created_at")?,
+        })
+    }
+}
+
+// Returns the id of the new entry
+pub async fn insert_entry(
+    client: &impl GenericClient,
+    kind: LedgerEntryKind,
+    reference_id: Option<i32>,
+    description: Option<&str>,
+) -> Result<i32, Error> {
+    let row = client
+        .query_one(
+            "INSERT INTO ledger_entries (kind, reference_id, description) VALUES ($1, $2, $3) RETURNING entry_id",
+            &[&kind, &reference_id, &description],
+        )
+        .await?;
+    row.try_get(0)
+}
+
+// `user_id` is set for postings to a user's account and only for those
+pub async fn insert_posting(
+    client: &impl GenericClient,
+    entry_id: i32,
+    account: &str,
+    user_id: Option<i32>,
+    asset: &str,
+    amount: Decimal,
+) -> Result<(), Error> {
+    client
+        .execute(
+            "INSERT INTO ledger_postings (entry_id, account, user_id, asset, amount) VALUES ($1, $2, $3, $4, $5)",
+            &[&entry_id, &account, &user_id, &asset, &amount],
+        )
+        .await?;
+    Ok(())
+}
+
+// A user's postings, oldest first, with their running balance in each asset
+pub async fn statement(client: &impl GenericClient, user_id: i32, asset: Option<&str>) -> Result<Vec<StatementLine>, Error> {
+    let rows = client
+        .query(
+            "SELECT * FROM (
+                SELECT e.entry_id, e.kind, e.reference_id, e.description, p.asset, p.amount,
+                       SUM(p.amount) OVER (PARTITION BY p.asset ORDER BY p.posting_id) AS balance, e.created_at, p.posting_id
+                FROM ledger_postings p JOIN ledger_entries e ON e.entry_id = p.entry_id
+                WHERE p.account = 'user' AND p.user_id = $1
+             ) lines
+             WHERE $2::VARCHAR IS NULL OR asset = $2
+             ORDER BY posting_id",
+            &[&user_id, &asset],
+        )
+        .await?;
+    rows.iter().map(StatementLine::try_from).collect()
+}
+
+// Every balance, available plus locked, that differs from the sum of the postings to its
+// user's account in the asset
+pub async fn drifts(client: &impl GenericClient) -> Result<Vec<BalanceDrift>, Error> {
+    let rows = client
+        .query(
+            "SELECT COALESCE(b.user_id, l.user_id), COALESCE(b.asset, l.asset),
+                    COALESCE(b.available + b.locked, 0), COALESCE(l.total, 0)
+             FROM balances b
+             FULL OUTER JOIN (
+                SELECT user_id, asset, SUM(amount) AS total FROM ledger_postings
+                WHERE account = 'user' GROUP BY user_id, asset
+             ) l ON l.user_id = b.user_id AND l.asset = b.asset
+             WHERE COALESCE(b.available + b.locked, 0) <> COALESCE(l.total, 0)
+             ORDER BY 1, 2",
+            &[],
+        )
+        .await?;
+    rows.iter()
+        .map(|row| {
+            Ok(BalanceDrift {
+                user_id: row.try_get(0)?,
+                asset: row.try_get(1)?,
+                balance: row.try_get(2)?,
+                ledger: row.try_get(3)?,
+            })
+        })
+        .collect()
+}
+
+// Entries whose postings do not sum to zero in some asset
+pub async fn unbalanced_entries(client: &impl GenericClient) -> Result<Vec<i32>, Error> {
+    let rows = client
+        .query(
+            "SELECT DISTINCT entry_id FROM ledger_postings GROUP BY entry_id, asset HAVING SUM(amount) <> 0 ORDER BY entry_id",
+            &[],
+        )
+        .await?;
+    rows.iter().map(|row| row.try_get(0)).collect()
+}
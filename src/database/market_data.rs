@@ -0,0 +1,144 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use tokio_postgres::{Error, Row};
+use super::{optional_utc_timestamp, utc_timestamp, GenericClient};
+use crate::models::{InstrumentId, MarketData, QuoteLevel};
+
+// The columns `MarketData` is read from
+pub const MARKET_DATA_COLUMNS: &str =
+    "symbol, best_bid, best_ask, mid_price, last_trade_price, last_trade_time, updated_at";
+
+// Recomputes the best prices of a symbol from its stored levels
+const REFRESH_BEST_PRICES: &str =
+    "INSERT INTO market_data (symbol, best_bid, best_ask, mid_price, updated_at)
+     SELECT $1, b.price, a.price, (b.price + a.price) / 2, CURRENT_TIMESTAMP
+     FROM (SELECT MAX(price) AS price FROM order_book_entries WHERE symbol = $1 AND side = 'bid') b,
+          (SELECT MIN(price) AS price FROM order_book_entries WHERE symbol = $1 AND side = 'ask') a
+     ON CONFLICT (symbol) DO UPDATE SET best_bid = EXCLUDED.best_bid, best_ask = EXCLUDED.best_ask,
+        mid_price = EXCLUDED.mid_price, updated_at = CURRENT_TIMESTAMP";
+
+impl TryFrom<&Row> for MarketData {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(MarketData {
+            symbol: row.try_get("symbol")?,
+            best_bid: row.try_get("best_bid")?,
+            best_ask: row.try_get("best_ask")?,
+            mid_price: row.try_get("mid_price")?,
+            last_trade_price: row.try_get("last_trade_price")?,
+            last_trade_time: optional_utc_timestamp(row, "last_trade_time")?,
+            updated_at: utc_timestamp(row, "updated_at")?,
+        })
+    }
+}
+
+pub async fn find(client: &impl GenericClient, symbol: &InstrumentId) -> Result<Option<MarketData>, Error> {
+    let row = client
+        .query_opt(&format!("SELECT {} FROM market_data WHERE symbol = $1", MARKET_DATA_COLUMNS), &[&symbol])
+        .await?;
+    row.as_ref().map(MarketData::try_from).transpose()
+}
+
+// Every symbol that has traded, with the price of its last trade
+pub async fn last_trade_prices(client: &impl GenericClient) -> Result<Vec<(InstrumentId, Decimal)>, Error> {
+    let rows = client
+        .query("SELECT symbol, last_trade_price FROM market_data WHERE last_trade_price IS NOT NULL", &[])
+        .await?;
+    rows.iter().map(|row| Ok((row.try_get(0)?, row.try_get(1)?))).collect()
+}
+
+// Adds the row of a new market, which has no prices yet
+pub async fn insert_empty(client: &impl GenericClient, symbol: &InstrumentId) -> Result<(), Error> {
+    client
+        .execute("INSERT INTO market_data (symbol) VALUES ($1) ON CONFLICT DO NOTHING", &[&symbol])
+        .await?;
+    Ok(())
+}
+
+pub async fn record_last_trade(
+    client: &impl GenericClient,
+    symbol: &InstrumentId,
+    price: Decimal,
+    timestamp: DateTime<Utc>,
+) -> Result<(), Error> {
+    client
+        .execute(
+            "INSERT INTO market_data (symbol, last_trade_price, last_trade_time) VALUES ($1, $2, $3)
+             ON CONFLICT (symbol) DO UPDATE SET last_trade_price = EXCLUDED.last_trade_price,
+                last_trade_time = EXCLUDED.last_trade_time, updated_at = CURRENT_TIMESTAMP",
+            &[&symbol, &price, &timestamp.naive_utc()],
+        )
+        .await?;
+    Ok(())
+}
+
+// The best `depth` stored levels of one side of a book, best first
+pub async fn book_levels(
+    client: &impl GenericClient,
+    symbol: &InstrumentId,
+    side: &str,
+    depth: usize,
+) -> Result<Vec<QuoteLevel>, Error> {
+    let order = if side == "bid" { "DESC" } else { "ASC" };
+    let rows = client
+        .query(
+            &format!(
+                "SELECT price, total_quantity, order_count FROM order_book_entries
+                 WHERE symbol = $1 AND side = $2 ORDER BY price {} LIMIT $3",
+                order
+            ),
+            &[&symbol, &side, &(depth as i64)],
+        )
+        .await?;
+    rows.iter()
+        .map(|row| {
+            Ok(QuoteLevel {
+                price: row.try_get("price")?,
+                quantity: row.try_get("total_quantity")?,
+                order_count: row.try_get::<_, i32>("order_count")? as usize,
+            })
+        })
+        .collect()
+}
+
+// Removes every stored level of a book, before it is written out again in full
+pub async fn clear_book(client: &impl GenericClient, symbol: &InstrumentId) -> Result<(), Error> {
+    client.execute("DELETE FROM order_book_entries WHERE symbol = $1", &[&symbol]).await?;
+    Ok(())
+}
+
+// Stores changed levels of one side of a book; a level without orders is removed. Refresh
+// the best prices once both sides are written.
+pub async fn write_levels(
+    client: &impl GenericClient,
+    symbol: &InstrumentId,
+    side: &str,
+    levels: &[QuoteLevel],
+) -> Result<(), Error> {
+    for level in levels {
+        if level.order_count == 0 {
+            client
+                .execute(
+                    "DELETE FROM order_book_entries WHERE symbol = $1 AND side = $2 AND price = $3",
+                    &[&symbol, &side, &level.price],
+                )
+                .await?;
+        } else {
+            client
+                .execute(
+                    "INSERT INTO order_book_entries (symbol, side, price, total_quantity, order_count) VALUES ($1, $2, $3, $4, $5)
+                     ON CONFLICT (symbol, side, price) DO UPDATE SET total_quantity = EXCLUDED.total_quantity,
+                        order_count = EXCLUDED.order_count, updated_at = CURRENT_TIMESTAMP",
+                    &[&symbol, &side, &level.price, &level.quantity, &(level.order_count as i32)],
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn refresh_best_prices(client: &impl GenericClient, symbol: &InstrumentId) -> Result<(), Error> {
+    client.execute(REFRESH_BEST_PRICES, &[&symbol]).await?;
+    Ok(())
+}
@@ -1,3 +1,26 @@
-pub mod connection;
+pub mod pool;
+pub mod users;
+pub mod orders;
+pub mod trades;
+pub mod positions;
+pub mod market_data;
+pub mod balances;
+pub mod ledger;
+pub mod fee_tiers;
+pub mod trade_fees;
+pub mod instruments;
 
-pub use connection::DatabaseConnection;
\ No newline at end of file
+pub use pool::Database;
+pub use deadpool_postgres::{Client, GenericClient, PoolError, Transaction};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use tokio_postgres::Row;
+
+// Timestamp columns have no time zone and hold UTC
+pub fn utc_timestamp(row: &Row, column: &str) -> Result<DateTime<Utc>, tokio_postgres::Error> {
+    Ok(row.try_get::<_, NaiveDateTime>(column)?.and_utc())
+}
+
+pub fn optional_utc_timestamp(row: &Row, column: &str) -> Result<Option<DateTime<Utc>>, tokio_postgres::Error> {
+    Ok(row.try_get::<_, Option<NaiveDateTime>>(column)?.map(|timestamp| timestamp.and_utc()))
+}
@@ -0,0 +1,285 @@
+use rust_decimal::Decimal;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Error, Row};
+use super::{utc_timestamp, GenericClient};
+use crate::models::{CreateOrderRequest, InstrumentId, Order, OrderSide, OrderStatus, RejectReason, TimeInForce};
+
+// The columns an `Order` is read from
+pub const ORDER_COLUMNS: &str = "order_id, user_id, symbol, side, order_type, quantity, limit_price, filled_quantity, \
+    remaining_quantity, status, time_in_force, submission_time, updated_at, stop_price, reject_reason";
+
+// Unfiltered listings return this many of the newest orders
+const LIST_LIMIT: i64 = 100;
+
+// What a finished order still had locked, taken off it to be returned to its owner
+#[derive(Debug, Clone)]
+pub struct ReleasedLock {
+    pub user_id: i32,
+    pub symbol: InstrumentId,
+    pub side: OrderSide,
+    pub amount: Decimal,
+}
+
+impl TryFrom<&Row> for Order {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(Order {
+            order_id: row.try_get("order_id")?,
+            user_id: row.try_get("user_id")?,
+            symbol: row.try_get("symbol")?,
+            side: row.try_get("side")?,
+            order_type: row.try_get("order_type")?,
+            quantity: row.try_get("quantity")?,
+            limit_price: row.try_get("limit_price")?,
+            stop_price: row.try_get("stop_price")?,
+            filled_quantity: row.try_get("filled_quantity")?,
+            remaining_quantity: row.try_get("remaining_quantity")?,
+            status: row.try_get("status")?,
+            reject_reason: row.try_get("reject_reason")?,
+            time_in_force: row.try_get("time_in_force")?,
+            submission_time: utc_timestamp(row, "submission_time")?,
+            updated_at: utc_timestamp(row, "updated_at")?,
+        })
+    }
+}
+
+// Records a new order, pending or rejected with its reason
+pub async fn insert(
+    client: &impl GenericClient,
+    request: &CreateOrderRequest,
+    time_in_force: TimeInForce,
+    status: OrderStatus,
+    reject_reason: Option<RejectReason>,
+) -> Result<Order, Error> {
+    let row = client
+        .query_one(
+            &format!(
+                "INSERT INTO orders (user_id, symbol, side, order_type, quantity, limit_price, remaining_quantity, time_in_force, stop_price, status, reject_reason)
+                 VALUES ($1, $2, $3, $4, $5, $6, $5, $7, $8, $9, $10)
+                 RETURNING {}",
+                ORDER_COLUMNS
+            ),
+            &[
+                &request.user_id,
+                &request.symbol,
+                &request.side,
+                &request.order_type,
+                &request.quantity,
+                &request.limit_price,
+                &time_in_force,
+                &request.stop_price,
+                &status,
+                &reject_reason,
+            ],
+        )
+        .await?;
+    Order::try_from(&row)
+}
+
+// Newest first, optionally only of one symbol or one user
+pub async fn find(client: &impl GenericClient, symbol: Option<&InstrumentId>, user_id: Option<i32>) -> Result<Vec<Order>, Error> {
+    let mut conditions = Vec::new();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    if let Some(symbol) = &symbol {
+        params.push(symbol);
+        conditions.push(format!("symbol = ${}", params.len()));
+    }
+    if let Some(user_id) = &user_id {
+        params.push(user_id);
+        conditions.push(format!("user_id = ${}", params.len()));
+    }
+
+    let query = if conditions.is_empty() {
+        format!("SELECT {} FROM orders ORDER BY submission_time DESC LIMIT {}", ORDER_COLUMNS, LIST_LIMIT)
+    } else {
+        format!("SELECT {} FROM orders WHERE {} ORDER BY submission_time DESC", ORDER_COLUMNS, conditions.join(" AND "))
+    };
+    let rows = client.query(&query, &params).await?;
+    rows.iter().map(Order::try_from).collect()
+}
+
+pub async fn find_by_id(client: &impl GenericClient, order_id: i32) -> Result<Option<Order>, Error> {
+    let row = client
+        .query_opt(&format!("SELECT {} FROM orders WHERE order_id = $1", ORDER_COLUMNS), &[&order_id])
+        .await?;
+    row.as_ref().map(Order::try_from).transpose()
+}
+
+// Orders that may still trade, oldest first
+pub async fn find_open(client: &impl GenericClient) -> Result<Vec<Order>, Error> {
+    let rows = client
+        .query(
+            &format!(
+                "SELECT {} FROM orders WHERE status IN ('pending', 'active') AND remaining_quantity > 0
+                 ORDER BY submission_time, order_id",
+                ORDER_COLUMNS
+            ),
+            &[],
+        )
+        .await?;
+    rows.iter().map(Order::try_from).collect()
+}
+
+// The symbol of one of the user's orders, if it is still pending or active
+pub async fn open_symbol(client: &impl GenericClient, order_id: i32, user_id: i32) -> Result<Option<InstrumentId>, Error> {
+    let row = client
+        .query_opt(
+            "SELECT symbol FROM orders WHERE order_id = $1 AND user_id = $2 AND status IN ('pending', 'active')",
+            &[&order_id, &user_id],
+        )
+        .await?;
+    row.map(|row| row.try_get(0)).transpose()
+}
+
+// None unless the order is the user's and still pending or active
+pub async fn cancel(client: &impl GenericClient, order_id: i32, user_id: i32) -> Result<Option<Order>, Error> {
+    let row = client
+        .query_opt(
+            &format!(
+                "UPDATE orders SET status = 'cancelled', updated_at = CURRENT_TIMESTAMP
+                 WHERE order_id = $1 AND user_id = $2 AND status IN ('pending', 'active')
+                 RETURNING {}",
+                ORDER_COLUMNS
+            ),
+            &[&order_id, &user_id],
+        )
+        .await?;
+    row.as_ref().map(Order::try_from).transpose()
+}
+
+// Adds a fill to an order resting on the book, which is filled once nothing remains. A fill
+// matched before a cancel can be written after it; the order stays cancelled.
+pub async fn fill_resting(client: &impl GenericClient, order_id: i32, size: Decimal) -> Result<(), Error> {
+    client
+        .execute(
+            "UPDATE orders SET filled_quantity = filled_quantity + $1, remaining_quantity = remaining_quantity - $1,
+             status = CASE WHEN status = 'cancelled' THEN 'cancelled' WHEN remaining_quantity - $1 <= 0 THEN 'filled' ELSE 'active' END,
+             updated_at = CURRENT_TIMESTAMP
+             WHERE order_id = $2",
+            &[&size, &order_id],
+        )
+        .await?;
+    Ok(())
+}
+
+// Adds a fill to an order without touching its status
+pub async fn fill(client: &impl GenericClient, order_id: i32, size: Decimal) -> Result<(), Error> {
+    client
+        .execute(
+            "UPDATE orders SET filled_quantity = filled_quantity + $1, remaining_quantity = remaining_quantity - $1, updated_at = CURRENT_TIMESTAMP
+             WHERE order_id = $2",
+            &[&size, &order_id],
+        )
+        .await?;
+    Ok(())
+}
+
+// A triggered stop is filled, resting on the book, or cancelled
+pub async fn settle_triggered(client: &impl GenericClient, order_id: i32, resting: bool) -> Result<(), Error> {
+    client
+        .execute(
+            "UPDATE orders SET status = CASE WHEN status = 'cancelled' THEN 'cancelled' WHEN remaining_quantity <= 0 THEN 'filled'
+             WHEN $2 THEN 'active' ELSE 'cancelled' END,
+             updated_at = CURRENT_TIMESTAMP WHERE order_id = $1",
+            &[&order_id, &resting],
+        )
+        .await?;
+    Ok(())
+}
+
+// Writes the outcome of matching an order: `filled` is what it traded while being matched,
+// added to whatever fills of orders matched after it have already written against it. Like
+// `fill_resting`, it leaves an order that was cancelled meanwhile cancelled.
+pub async fn save_execution(client: &impl GenericClient, order: &Order, filled: Decimal) -> Result<Order, Error> {
+    let row = client
+        .query_one(
+            &format!(
+                "UPDATE orders SET filled_quantity = filled_quantity + $1, remaining_quantity = remaining_quantity - $1,
+                 status = CASE WHEN status = 'cancelled' THEN 'cancelled' WHEN remaining_quantity - $1 <= 0 THEN 'filled' ELSE $2 END,
+                 reject_reason = $3, updated_at = CURRENT_TIMESTAMP
+                 WHERE order_id = $4 RETURNING {}",
+                ORDER_COLUMNS
+            ),
+            &[&filled, &order.status, &order.reject_reason, &order.order_id],
+        )
+        .await?;
+    Order::try_from(&row)
+}
+
+// Rejects an order that is still pending, such as one the engine refused
+pub async fn reject(client: &impl GenericClient, order_id: i32, reason: RejectReason) -> Result<(), Error> {
+    client
+        .execute(
+            "UPDATE orders SET status = 'rejected', reject_reason = $2, updated_at = CURRENT_TIMESTAMP
+             WHERE order_id = $1 AND status = 'pending'",
+            &[&order_id, &reason],
+        )
+        .await?;
+    Ok(())
+}
+
+// Cancels the DAY orders the engine expired, and DAY stops still waiting for their trigger,
+// and returns their ids. Other pending orders are left to the request matching them.
+pub async fn expire_day_orders(client: &impl GenericClient, expired: &[i32]) -> Result<Vec<i32>, Error> {
+    let rows = client
+        .query(
+            "UPDATE orders SET status = 'cancelled', updated_at = CURRENT_TIMESTAMP
+             WHERE time_in_force = 'DAY' AND status IN ('pending', 'active')
+             AND (order_id = ANY($1) OR (status = 'pending' AND stop_price IS NOT NULL))
+             RETURNING order_id",
+            &[&expired],
+        )
+        .await?;
+    rows.iter().map(|row| row.try_get(0)).collect()
+}
+
+// Records what an accepted order has locked in its owner's balance
+pub async fn set_locked_amount(client: &impl GenericClient, order_id: i32, amount: Decimal) -> Result<(), Error> {
+    client
+        .execute("UPDATE orders SET locked_amount = $1 WHERE order_id = $2", &[&amount, &order_id])
+        .await?;
+    Ok(())
+}
+
+// What the order still has locked, with the row locked until the end of the transaction so
+// concurrent fills spend it in turn
+pub async fn locked_amount_for_update(client: &impl GenericClient, order_id: i32) -> Result<Decimal, Error> {
+    let row = client
+        .query_one("SELECT locked_amount FROM orders WHERE order_id = $1 FOR UPDATE", &[&order_id])
+        .await?;
+    row.try_get(0)
+}
+
+pub async fn spend_locked_amount(client: &impl GenericClient, order_id: i32, amount: Decimal) -> Result<(), Error> {
+    client
+        .execute("UPDATE orders SET locked_amount = locked_amount - $1 WHERE order_id = $2", &[&amount, &order_id])
+        .await?;
+    Ok(())
+}
+
+// Clears what the given orders that have been filled, cancelled or rejected still lock
+pub async fn take_finished_locks(client: &impl GenericClient, order_ids: &[i32]) -> Result<Vec<ReleasedLock>, Error> {
+    let rows = client
+        .query(
+            "WITH finished AS (
+                SELECT order_id, user_id, symbol, side, locked_amount FROM orders
+                WHERE order_id = ANY($1) AND status IN ('filled', 'cancelled', 'rejected') AND locked_amount > 0
+                FOR UPDATE
+             )
+             UPDATE orders SET locked_amount = 0 FROM finished WHERE orders.order_id = finished.order_id
+             RETURNING finished.user_id, finished.symbol, finished.side, finished.locked_amount",
+            &[&order_ids],
+        )
+        .await?;
+    rows.iter()
+        .map(|row| {
+            Ok(ReleasedLock {
+                user_id: row.try_get("user_id")?,
+                symbol: row.try_get("symbol")?,
+                side: row.try_get("side")?,
+                amount: row.try_get("locked_amount")?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,110 @@
+use rust_decimal::Decimal;
+use tokio_postgres::{Error, Row};
+use super::{utc_timestamp, GenericClient};
+use crate::models::{InstrumentId, Position};
+
+// The columns a `Position` is read from
+pub const POSITION_COLUMNS: &str = "position_id, user_id, symbol, quantity, avg_cost, updated_at";
+
+// Positions joined with their mark: the last trade price, or the mid price before the
+// first trade. Flat positions are left out.
+const POSITIONS_WITH_MARKS: &str =
+    "SELECT p.user_id, p.symbol, p.quantity, p.avg_cost, COALESCE(m.last_trade_price, m.mid_price) AS mark
+     FROM positions p LEFT JOIN market_data m ON m.symbol = p.symbol
+     WHERE p.quantity <> 0";
+
+// An open position with the price it is marked at, if the symbol has one yet
+#[derive(Debug, Clone)]
+pub struct MarkedPosition {
+    pub user_id: i32,
+    pub symbol: InstrumentId,
+    pub quantity: Decimal,
+    pub avg_cost: Decimal,
+    pub mark: Option<Decimal>,
+}
+
+impl TryFrom<&Row> for Position {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(Position {
+            position_id: row.try_get("position_id")?,
+            user_id: row.try_get("user_id")?,
+            symbol: row.try_get("symbol")?,
+            quantity: row.try_get("quantity")?,
+            avg_cost: row.try_get("avg_cost")?,
+            updated_at: utc_timestamp(row, "updated_at")?,
+        })
+    }
+}
+
+impl TryFrom<&Row> for MarkedPosition {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(MarkedPosition {
+            user_id: row.try_get("user_id")?,
+            symbol: row.try_get("symbol")?,
+            quantity: row.try_get("quantity")?,
+            avg_cost: row.try_get("avg_cost")?,
+            mark: row.try_get("mark")?,
+        })
+    }
+}
+
+pub async fn for_user(client: &impl GenericClient, user_id: i32) -> Result<Vec<Position>, Error> {
+    let rows = client
+        .query(
+            &format!("SELECT {} FROM positions WHERE user_id = $1 ORDER BY symbol", POSITION_COLUMNS),
+            &[&user_id],
+        )
+        .await?;
+    rows.iter().map(Position::try_from).collect()
+}
+
+// Locks the position until the end of the transaction, so concurrent trades apply to it
+// one after the other
+pub async fn find_for_update(
+    client: &impl GenericClient,
+    user_id: i32,
+    symbol: &InstrumentId,
+) -> Result<Option<Position>, Error> {
+    let row = client
+        .query_opt(
+            &format!("SELECT {} FROM positions WHERE user_id = $1 AND symbol = $2 FOR UPDATE", POSITION_COLUMNS),
+            &[&user_id, &symbol],
+        )
+        .await?;
+    row.as_ref().map(Position::try_from).transpose()
+}
+
+pub async fn save(
+    client: &impl GenericClient,
+    user_id: i32,
+    symbol: &InstrumentId,
+    quantity: Decimal,
+    avg_cost: Decimal,
+) -> Result<(), Error> {
+    client
+        .execute(
+            "INSERT INTO positions (user_id, symbol, quantity, avg_cost) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (user_id, symbol) DO UPDATE SET quantity = EXCLUDED.quantity, avg_cost = EXCLUDED.avg_cost, updated_at = CURRENT_TIMESTAMP",
+            &[&user_id, &symbol, &quantity, &avg_cost],
+        )
+        .await?;
+    Ok(())
+}
+
+// The user's open positions with their marks, by symbol
+pub async fn marked_for_user(client: &impl GenericClient, user_id: i32) -> Result<Vec<MarkedPosition>, Error> {
+    let rows = client
+        .query(&format!("{} AND p.user_id = $1 ORDER BY p.symbol", POSITIONS_WITH_MARKS), &[&user_id])
+        .await?;
+    rows.iter().map(MarkedPosition::try_from).collect()
+}
+
+// Every user's open positions with their marks
+pub async fn all_marked(client: &impl GenericClient) -> Result<Vec<MarkedPosition>, Error> {
+    let rows = client.query(POSITIONS_WITH_MARKS, &[]).await?;
+    rows.iter().map(MarkedPosition::try_from).collect()
+}
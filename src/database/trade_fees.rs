@@ -0,0 +1,23 @@
+use rust_decimal::Decimal;
+use tokio_postgres::Error;
+use super::GenericClient;
+use crate::models::Liquidity;
+
+// Records the fee one side of a trade was charged, at the rate it was charged at
+pub async fn insert(
+    client: &impl GenericClient,
+    trade_id: i32,
+    user_id: i32,
+    order_id: i32,
+    liquidity: Liquidity,
+    rate: Decimal,
+    fee: Decimal,
+) -> Result<(), Error> {
+    client
+        .execute(
+            "INSERT INTO trade_fees (trade_id, user_id, order_id, liquidity, rate, fee) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&trade_id, &user_id, &order_id, &liquidity, &rate, &fee],
+        )
+        .await?;
+    Ok(())
+}
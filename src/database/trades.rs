@@ -0,0 +1,132 @@
+use rust_decimal::Decimal;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Error, Row};
+use super::{utc_timestamp, GenericClient};
+use crate::matching_engine::orderbook::Fill;
+use crate::models::{InstrumentId, OrderSide, Trade, UserTrade};
+
+// The columns a `Trade` is read from
+pub const TRADE_COLUMNS: &str =
+    "trade_id, symbol, price, quantity, buy_order_id, sell_order_id, buyer_user_id, seller_user_id, aggressor_side, timestamp";
+
+// Unfiltered listings return this many of the newest trades
+const LIST_LIMIT: i64 = 100;
+
+impl TryFrom<&Row> for Trade {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(Trade {
+            trade_id: row.try_get("trade_id")?,
+            symbol: row.try_get("symbol")?,
+            price: row.try_get("price")?,
+            quantity: row.try_get("quantity")?,
+            buy_order_id: row.try_get("buy_order_id")?,
+            sell_order_id: row.try_get("sell_order_id")?,
+            buyer_user_id: row.try_get("buyer_user_id")?,
+            seller_user_id: row.try_get("seller_user_id")?,
+            aggressor_side: row.try_get("aggressor_side")?,
+            timestamp: utc_timestamp(row, "timestamp")?,
+        })
+    }
+}
+
+// Records a fill and returns the id of its trade
+pub async fn insert(client: &impl GenericClient, symbol: &InstrumentId, fill: &Fill) -> Result<i32, Error> {
+    let row = client
+        .query_one(
+            "INSERT INTO trades (symbol, price, quantity, buy_order_id, sell_order_id, buyer_user_id, seller_user_id, aggressor_side, timestamp)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             RETURNING trade_id",
+            &[
+                &symbol,
+                &fill.price,
+                &fill.size,
+                &fill.buy_order_id(),
+                &fill.sell_order_id(),
+                &fill.buyer_user_id(),
+                &fill.seller_user_id(),
+                &fill.aggressor_side(),
+                &fill.timestamp.naive_utc(),
+            ],
+        )
+        .await?;
+    row.try_get(0)
+}
+
+// Whether a fill has been recorded; fills carry the engine time of the command that matched
+// them, so a fill replayed from the journal is recorded with the same timestamp
+pub async fn exists(client: &impl GenericClient, fill: &Fill) -> Result<bool, Error> {
+    let row = client
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM trades WHERE buy_order_id = $1 AND sell_order_id = $2 AND quantity = $3 AND timestamp = $4)",
+            &[&fill.buy_order_id(), &fill.sell_order_id(), &fill.size, &fill.timestamp.naive_utc()],
+        )
+        .await?;
+    row.try_get(0)
+}
+
+// Newest first, optionally only of one symbol or with one user on either side
+pub async fn find(client: &impl GenericClient, symbol: Option<&InstrumentId>, user_id: Option<i32>) -> Result<Vec<Trade>, Error> {
+    let mut conditions = Vec::new();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    if let Some(symbol) = &symbol {
+        params.push(symbol);
+        conditions.push(format!("symbol = ${}", params.len()));
+    }
+    if let Some(user_id) = &user_id {
+        params.push(user_id);
+        conditions.push(format!("(buyer_user_id = ${0} OR seller_user_id = ${0})", params.len()));
+    }
+
+    let query = if conditions.is_empty() {
+        format!("SELECT {} FROM trades ORDER BY timestamp DESC LIMIT {}", TRADE_COLUMNS, LIST_LIMIT)
+    } else {
+        format!("SELECT {} FROM trades WHERE {} ORDER BY timestamp DESC", TRADE_COLUMNS, conditions.join(" AND "))
+    };
+    let rows = client.query(&query, &params).await?;
+    rows.iter().map(Trade::try_from).collect()
+}
+
+// The user's side of each of their trades with the fees they paid on it, newest first
+pub async fn user_history(client: &impl GenericClient, user_id: i32) -> Result<Vec<UserTrade>, Error> {
+    let rows = client
+        .query(
+            "SELECT t.trade_id, t.symbol, t.price, t.quantity, t.buy_order_id, t.sell_order_id, t.buyer_user_id, t.timestamp,
+                    COALESCE(SUM(f.fee), 0) AS fees
+             FROM trades t LEFT JOIN trade_fees f ON f.trade_id = t.trade_id AND f.user_id = $1
+             WHERE t.buyer_user_id = $1 OR t.seller_user_id = $1
+             GROUP BY t.trade_id ORDER BY t.timestamp DESC",
+            &[&user_id],
+        )
+        .await?;
+
+    rows.iter()
+        .map(|row| {
+            let bought = row.try_get::<_, i32>("buyer_user_id")? == user_id;
+            Ok(UserTrade {
+                trade_id: row.try_get("trade_id")?,
+                symbol: row.try_get("symbol")?,
+                side: if bought { OrderSide::Buy } else { OrderSide::Sell },
+                price: row.try_get("price")?,
+                quantity: row.try_get("quantity")?,
+                order_id: row.try_get(if bought { "buy_order_id" } else { "sell_order_id" })?,
+                timestamp: utc_timestamp(row, "timestamp")?,
+                fees: row.try_get("fees")?,
+            })
+        })
+        .collect()
+}
+
+// The notional the user traded in the symbol over the last `days` days
+pub async fn trailing_volume(client: &impl GenericClient, user_id: i32, symbol: &InstrumentId, days: i32) -> Result<Decimal, Error> {
+    let row = client
+        .query_one(
+            "SELECT COALESCE(SUM(price * quantity), 0) FROM trades
+             WHERE symbol = $1 AND (buyer_user_id = $2 OR seller_user_id = $2)
+             AND timestamp > CURRENT_TIMESTAMP - make_interval(days => $3)",
+            &[&symbol, &user_id, &days],
+        )
+        .await?;
+    row.try_get(0)
+}
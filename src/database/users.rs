@@ -0,0 +1,71 @@
+use rust_decimal::Decimal;
+use tokio_postgres::{Error, Row};
+use super::{utc_timestamp, GenericClient};
+use crate::models::User;
+
+// The columns a `User` is read from. `cash_balance` was a column before balances were held
+// per asset and is now the available USD balance.
+pub const USER_COLUMNS: &str = "user_id, username, realized_pnl, unrealized_pnl, created_at, updated_at, \
+    COALESCE((SELECT available FROM balances WHERE balances.user_id = users.user_id AND asset = 'USD'), 0) AS cash_balance";
+
+impl TryFrom<&Row> for User {
+    type Error = Error;
+
+    fn try_from(row: &Row) -> Result<Self, Error> {
+        Ok(User {
+            user_id: row.try_get("user_id")?,
+            username: row.try_get("username")?,
+            cash_balance: row.try_get("cash_balance")?,
+            realized_pnl: row.try_get("realized_pnl")?,
+            unrealized_pnl: row.try_get("unrealized_pnl")?,
+            created_at: utc_timestamp(row, "created_at")?,
+            updated_at: utc_timestamp(row, "updated_at")?,
+        })
+    }
+}
+
+pub async fn insert(client: &impl GenericClient, username: &str) -> Result<User, Error> {
+    let row = client
+        .query_one(
+            &format!("INSERT INTO users (username) VALUES ($1) RETURNING {}", USER_COLUMNS),
+            &[&username],
+        )
+        .await?;
+    User::try_from(&row)
+}
+
+pub async fn find(client: &impl GenericClient, user_id: i32) -> Result<Option<User>, Error> {
+    let row = client
+        .query_opt(&format!("SELECT {} FROM users WHERE user_id = $1", USER_COLUMNS), &[&user_id])
+        .await?;
+    row.as_ref().map(User::try_from).transpose()
+}
+
+pub async fn exists(client: &impl GenericClient, user_id: i32) -> Result<bool, Error> {
+    let row = client.query_opt("SELECT user_id FROM users WHERE user_id = $1", &[&user_id]).await?;
+    Ok(row.is_some())
+}
+
+pub async fn add_realized_pnl(client: &impl GenericClient, user_id: i32, amount: Decimal) -> Result<(), Error> {
+    client
+        .execute(
+            "UPDATE users SET realized_pnl = realized_pnl + $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
+            &[&amount, &user_id],
+        )
+        .await?;
+    Ok(())
+}
+
+// Sets every user's unrealized P&L; users left out of `totals` go back to zero
+pub async fn set_unrealized_pnl(client: &impl GenericClient, totals: &[(i32, Decimal)]) -> Result<(), Error> {
+    client.execute("UPDATE users SET unrealized_pnl = 0 WHERE unrealized_pnl <> 0", &[]).await?;
+    for (user_id, total) in totals {
+        client
+            .execute(
+                "UPDATE users SET unrealized_pnl = $1, updated_at = CURRENT_TIMESTAMP WHERE user_id = $2",
+                &[total, user_id],
+            )
+            .await?;
+    }
+    Ok(())
+}
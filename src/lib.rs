@@ -0,0 +1,4 @@
+pub mod database;
+pub mod server;
+pub mod models;
+pub mod matching_engine;
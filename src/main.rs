@@ -32,16 +32,12 @@
 
 
 
-mod database;
-mod server;
-mod models;
-
-use database::DatabaseConnection;
-use server::{create_app, start_server};
+use trading_engine::database::Database;
+use trading_engine::server::{create_app, start_server};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let db = DatabaseConnection::new().await?;
+    let db = Database::new()?;
     
     match db.test_connection().await {
         Ok(_) => println!("Successfully connected to PostgreSQL!"),
@@ -51,7 +47,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    let app = create_app(db).await;
+    let app = create_app(db).await?;
     start_server(app).await?;
 
     Ok(())
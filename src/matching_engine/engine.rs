@@ -1,52 +1,694 @@
 #![allow(dead_code)]
-use super::orderbook::{OrderBook,Order};
+use super::orderbook::{BidOrAsk,OrderBook,Order,Fill,Limit,DEFAULT_LOT_SIZE};
+use super::trigger_book::{StopOrder,TriggerBook};
+use super::journal::{Command,Journal,JournalRecord,LevelChange,Outcome};
+use super::snapshot::{EngineSnapshot,MarketSnapshot,RestingLevel};
+use crate::models::{InstrumentId,OrderBookSnapshot,QuoteLevel};
 use std::{collections::HashMap};
 use rust_decimal::prelude::*;
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Hash, Clone,Debug)]
-pub struct TradingPair{
-    base: String,
-    quote: String
+// How many fill events a slow subscriber may lag behind before it starts missing them
+const FILL_CHANNEL_CAPACITY: usize = 1024;
+const MARKET_DATA_CHANNEL_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct FillEvent {
+    pub instrument: InstrumentId,
+    pub fill: Fill,
 }
 
-impl TradingPair {
-    pub fn new(base: String, quote:String) ->Self{
-        TradingPair{
-            base, quote
-        }
-    }
-    pub fn to_string(&self) ->String{
-        format!("{}/{}",self.base,self.quote)
+// Public market data of one market. Every event carries the next number of the market's
+// sequence, so a subscriber that sees a gap knows it has to start over from a snapshot.
+#[derive(Debug, Clone)]
+pub struct MarketEvent {
+    pub instrument: InstrumentId,
+    pub sequence: u64,
+    pub update: MarketUpdate,
+}
+
+#[derive(Debug, Clone)]
+pub enum MarketUpdate {
+    // Levels whose size or order count changed; a level with no orders has been removed
+    Levels { bids: Vec<QuoteLevel>, asks: Vec<QuoteLevel> },
+    Trade(Fill),
+    // Sent once per matching cycle that traded, with the price of its last trade
+    Ticker { price: Decimal, timestamp: DateTime<Utc> },
+}
+
+// Outcome of one matching cycle: every fill in execution order, including those of stop
+// orders the cycle triggered, and what became of each triggered stop
+#[derive(Debug, Clone, Default)]
+pub struct Execution {
+    pub fills: Vec<Fill>,
+    pub triggered: Vec<TriggeredStop>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TriggeredStop {
+    pub order_id: i32,
+    // Whether a remainder of the (stop-limit) order is now resting on the book
+    pub resting: bool,
+}
+
+impl Execution {
+    fn from_fills(fills: Vec<Fill>) -> Self {
+        Execution { fills, triggered: Vec::new() }
     }
 }
+
 #[derive(Debug)]
 pub struct MatchingEngine {
-    orderbooks: HashMap<TradingPair,OrderBook>
+    orderbooks: HashMap<InstrumentId,OrderBook>,
+    triggers: HashMap<InstrumentId,TriggerBook>,
+    fills: broadcast::Sender<FillEvent>,
+    market_data: broadcast::Sender<MarketEvent>,
+    sequences: HashMap<InstrumentId,u64>,
+    journal: Option<Journal>,
+    // Time of the command being applied, read from the journal when replaying
+    clock: DateTime<Utc>,
+    // What the command being applied has produced so far
+    outcome: Outcome,
+}
+
+impl Default for MatchingEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MatchingEngine{
  pub fn new() -> Self {
+    let (fills, _) = broadcast::channel(FILL_CHANNEL_CAPACITY);
+    let (market_data, _) = broadcast::channel(MARKET_DATA_CHANNEL_CAPACITY);
     MatchingEngine{
         orderbooks:HashMap::new(),
+        triggers:HashMap::new(),
+        fills,
+        market_data,
+        sequences:HashMap::new(),
+        journal:None,
+        clock:Utc::now(),
+        outcome:Outcome::default(),
     }
  }
- pub fn add_new_market(&mut self, pair: TradingPair){
-    self.orderbooks.insert(pair.clone(), OrderBook::new());
-    print!("Opening new orderbook for market {:?}", pair.to_string());
+ // Every command is written to `journal` before it is applied
+ pub fn with_journal(journal: Journal) -> Self {
+    MatchingEngine{
+        journal:Some(journal),
+        ..Self::new()
+    }
  }
- pub fn place_limit_order(&mut self, pair: TradingPair, price:Decimal, order:Order) -> Result<(),String>{
-    match self.orderbooks.get_mut(&pair){
-        Some(orderbook) => {
-            orderbook.add_limit_order(price,order);
+ // Every fill produced by any market is published here in matching order
+ pub fn subscribe(&self) -> broadcast::Receiver<FillEvent>{
+    self.fills.subscribe()
+ }
+ fn publish(&self, instrument: &InstrumentId, fills: &[Fill]){
+    for fill in fills{
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.fills.send(FillEvent{ instrument: instrument.clone(), fill: fill.clone() });
+    }
+ }
+ // Market data of every market. Take the receiver before the snapshot it is applied to.
+ pub fn subscribe_market_data(&self) -> broadcast::Receiver<MarketEvent>{
+    self.market_data.subscribe()
+ }
+ // Lets whoever hands the engine to another thread keep taking market data receivers
+ pub fn market_data_sender(&self) -> broadcast::Sender<MarketEvent>{
+    self.market_data.clone()
+ }
+ // The top `depth` levels of each side, with the sequence number they are current as of
+ pub fn snapshot(&self, instrument: &InstrumentId, depth: usize) -> Result<(u64, OrderBookSnapshot),String>{
+    let Some(orderbook) = self.orderbooks.get(instrument) else {
+        return Err(format!("The order book for the given instrument ({})does not exist",instrument));
+    };
+    let quote = |limit: &Limit| QuoteLevel{
+        price: limit.price(),
+        quantity: limit.total_volume(),
+        order_count: limit.order_count(),
+    };
+    let (best_bid, best_ask) = (orderbook.best_bid(), orderbook.best_ask());
+    let snapshot = OrderBookSnapshot{
+        symbol: instrument.clone(),
+        bids: orderbook.bid_limits().take(depth).map(quote).collect(),
+        asks: orderbook.ask_limits().take(depth).map(quote).collect(),
+        best_bid,
+        best_ask,
+        mid_price: best_bid.zip(best_ask).map(|(bid, ask)| (bid + ask) / Decimal::TWO),
+        spread: orderbook.spread(),
+        timestamp: Utc::now(),
+    };
+    Ok((self.sequences.get(instrument).copied().unwrap_or(0), snapshot))
+ }
+ fn publish_market_data(&mut self, instrument: &InstrumentId, fills: &[Fill]){
+    let Some(orderbook) = self.orderbooks.get_mut(instrument) else {
+        return;
+    };
+    let mut updates: Vec<MarketUpdate> = fills.iter().cloned().map(MarketUpdate::Trade).collect();
+    if let Some(last) = fills.last(){
+        updates.push(MarketUpdate::Ticker{ price: last.price, timestamp: last.timestamp });
+    }
+
+    let mut changed = orderbook.take_changed_levels();
+    changed.sort_by_key(|(side, price)| (*price, *side));
+    let (mut bids, mut asks) = (Vec::new(), Vec::new());
+    for (side, price) in changed{
+        let level = orderbook.level(side, price);
+        let quote = QuoteLevel{
+            price,
+            quantity: level.map_or(Decimal::ZERO, |limit| limit.total_volume()),
+            order_count: level.map_or(0, |limit| limit.order_count()),
+        };
+        self.outcome.levels.push(LevelChange{
+            instrument: instrument.clone(),
+            side,
+            price,
+            quantity: quote.quantity,
+            order_count: quote.order_count,
+        });
+        match side{
+            BidOrAsk::Bid => bids.push(quote),
+            BidOrAsk::Ask => asks.push(quote),
+        }
+    }
+    if !bids.is_empty() || !asks.is_empty(){
+        bids.reverse();
+        updates.push(MarketUpdate::Levels{ bids, asks });
+    }
 
-            println!("Placed limit order @ price {}", price);
+    let sequence = self.sequences.entry(instrument.clone()).or_insert(0);
+    for update in updates{
+        *sequence += 1;
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.market_data.send(MarketEvent{ instrument: instrument.clone(), sequence: *sequence, update });
+    }
+ }
+ pub fn add_new_market(&mut self, instrument: InstrumentId){
+    self.add_new_market_with_lot_size(instrument, DEFAULT_LOT_SIZE);
+ }
+ pub fn add_new_market_with_lot_size(&mut self, instrument: InstrumentId, lot_size: Decimal){
+    if let Err(e) = self.submit(Command::OpenMarket{ instrument, lot_size }){
+        eprintln!("{}", e);
+    }
+ }
+ pub fn has_market(&self, instrument: &InstrumentId) -> bool{
+    self.orderbooks.contains_key(instrument)
+ }
+ // Whether the order rests on the market's book or waits in its trigger book
+ pub fn holds_order(&self, instrument: &InstrumentId, order_id: i32) -> bool{
+    self.orderbooks.get(instrument).is_some_and(|orderbook| orderbook.contains_order(order_id))
+        || self.triggers.get(instrument).is_some_and(|triggers| triggers.contains_order(order_id))
+ }
+ pub fn markets(&self) -> impl Iterator<Item = &InstrumentId>{
+    self.orderbooks.keys()
+ }
+ // Rebuilding after a restart: orders go back in the order they were first accepted and
+ // are not matched again, since they already were before they were saved
+ pub fn restore_limit_order(&mut self, instrument: &InstrumentId, price: Decimal, order: Order) -> Result<(),String>{
+    self.submit(Command::RestoreLimit{ instrument: instrument.clone(), price, order })
+ }
+ pub fn restore_stop_order(&mut self, instrument: &InstrumentId, stop_price: Decimal, limit_price: Option<Decimal>, order: Order) -> Result<(),String>{
+    self.submit(Command::RestoreStop{ instrument: instrument.clone(), stop_price, limit_price, order })
+ }
+ pub fn restore_last_trade_price(&mut self, instrument: &InstrumentId, price: Decimal) -> Result<(),String>{
+    self.submit(Command::RestoreLastTradePrice{ instrument: instrument.clone(), price })
+ }
+ // Every resting order, pending stop and market data sequence of every market. The
+ // snapshot also records how far into the journal it is, so that recovery only has to
+ // replay what came after it.
+ pub fn take_snapshot(&self) -> EngineSnapshot{
+    let mut instruments: Vec<&InstrumentId> = self.orderbooks.keys().collect();
+    instruments.sort();
+
+    let markets = instruments
+        .into_iter()
+        .map(|instrument| {
+            let orderbook = &self.orderbooks[instrument];
+            let triggers = self.triggers.get(instrument);
+            MarketSnapshot{
+                instrument: instrument.clone(),
+                lot_size: orderbook.lot_size(),
+                sequence: self.sequences.get(instrument).copied().unwrap_or(0),
+                last_trade_price: triggers.and_then(|triggers| triggers.last_trade_price()),
+                bids: orderbook.bid_limits().map(RestingLevel::from_limit).collect(),
+                asks: orderbook.ask_limits().map(RestingLevel::from_limit).collect(),
+                stops: triggers.map_or(Vec::new(), |triggers| triggers.stops().cloned().collect()),
+            }
+        })
+        .collect();
+
+    EngineSnapshot{
+        taken_at: Utc::now(),
+        journal_file: self.journal.as_ref().and_then(|journal| {
+            journal.path().file_name().map(|name| name.to_string_lossy().into_owned())
+        }),
+        journal_sequence: self.journal.as_ref().map_or(0, |journal| journal.last_sequence()),
+        markets,
+    }
+ }
+ // Loads a snapshot through the restore commands, so a journal started before this holds
+ // the state it was loaded with. Market data carries on from the snapshot's sequences.
+ pub fn restore_snapshot(&mut self, snapshot: &EngineSnapshot) -> Result<(),String>{
+    for market in &snapshot.markets{
+        let instrument = &market.instrument;
+        self.add_new_market_with_lot_size(instrument.clone(), market.lot_size);
+        for level in market.bids.iter().chain(&market.asks){
+            for order in &level.orders{
+                self.restore_limit_order(instrument, level.price, order.clone())?;
+            }
+        }
+        for stop in &market.stops{
+            self.restore_stop_order(instrument, stop.stop_price, stop.limit_price, stop.order.clone())?;
+        }
+        if let Some(price) = market.last_trade_price{
+            self.restore_last_trade_price(instrument, price)?;
+        }
+        self.sequences.insert(instrument.clone(), market.sequence);
+    }
+    Ok(())
+ }
+ // Rounds a quantity down to the lot size of the instrument's market
+ pub fn round_to_lot(&self, instrument: &InstrumentId, size: Decimal) -> Result<Decimal,String>{
+    match self.orderbooks.get(instrument){
+        Some(orderbook) => Ok(orderbook.round_to_lot(size)),
+        None => {
+            Err(format!("The order book for the given instrument ({})does not exist",instrument))
+        }
+    }
+ }
+ // Notional a market order would trade at against the current book
+ pub fn cost_to_fill(&self, instrument: &InstrumentId, side: BidOrAsk, size: Decimal) -> Result<Decimal,String>{
+    match self.orderbooks.get(instrument){
+        Some(orderbook) => Ok(orderbook.cost_to_fill(side, size)),
+        None => {
+            Err(format!("The order book for the given instrument ({})does not exist",instrument))
+        }
+    }
+ }
+ pub fn place_market_order(&mut self, instrument: InstrumentId, order: &mut Order) -> Result<Execution,String>{
+    self.record(&Command::PlaceMarket{ instrument: instrument.clone(), order: order.clone() })?;
+    let result = self.market_order(instrument, order);
+    self.record_outcome(&result);
+    result
+ }
+ pub fn place_limit_order(&mut self, instrument: InstrumentId, price:Decimal, order:Order) -> Result<Execution,String>{
+    self.record(&Command::PlaceLimit{ instrument: instrument.clone(), price, order: order.clone() })?;
+    let result = self.limit_order(instrument, price, order);
+    self.record_outcome(&result);
+    result
+ }
+ // Parks a stop until the market trades through `stop_price`; a stop that is already
+ // through the last trade price is triggered straight away
+ pub fn place_stop_order(&mut self, instrument: InstrumentId, stop_price: Decimal, limit_price: Option<Decimal>, order: Order) -> Result<Execution,String>{
+    self.record(&Command::PlaceStop{ instrument: instrument.clone(), stop_price, limit_price, order: order.clone() })?;
+    let result = self.stop_order(instrument, stop_price, limit_price, order);
+    self.record_outcome(&result);
+    result
+ }
+ // Removes a resting order, or a stop still waiting for its trigger
+ pub fn cancel_order(&mut self, instrument: InstrumentId, order_id: i32) -> Result<Order,String>{
+    self.record(&Command::Cancel{ instrument: instrument.clone(), order_id })?;
+    let result = self.cancel(instrument, order_id);
+    self.record_outcome(&result);
+    result
+ }
+ pub fn modify_order(&mut self, instrument: InstrumentId, order_id: i32, price: Decimal, size: Decimal) -> Result<Execution,String>{
+    self.record(&Command::Modify{ instrument: instrument.clone(), order_id, price, size })?;
+    let result = self.modify(instrument, order_id, price, size);
+    self.record_outcome(&result);
+    result
+ }
+ // Called at the session close: removes the DAY orders still resting or waiting for a
+ // trigger in every market
+ pub fn expire_day_orders(&mut self) -> Vec<(InstrumentId,Order)>{
+    if let Err(e) = self.record(&Command::SessionClose){
+        eprintln!("{}", e);
+        return Vec::new();
+    }
+    let expired = self.session_close();
+    self.record_outcome(&Ok(()));
+    expired
+ }
+
+ // Journals a command before it is applied, and fixes the engine time the command runs at
+ fn record(&mut self, command: &Command) -> Result<(),String>{
+    self.clock = Utc::now();
+    self.outcome = Outcome::default();
+    match self.journal.as_mut(){
+        Some(journal) => {
+            let record = JournalRecord::Command{ timestamp: self.clock, command: command.clone() };
+            journal.append(&record).map(|_| ()).map_err(|e| format!("Failed to journal the command: {}", e))
+        }
+        None => Ok(()),
+    }
+ }
+ fn record_outcome<T>(&mut self, result: &Result<T,String>){
+    let mut outcome = std::mem::take(&mut self.outcome);
+    outcome.error = result.as_ref().err().cloned();
+    if let Some(journal) = self.journal.as_mut(){
+        let record = JournalRecord::Outcome{ command_sequence: journal.last_sequence(), outcome };
+        if let Err(e) = journal.append(&record){
+            eprintln!("Failed to journal the outcome: {}", e);
+        }
+    }
+ }
+ fn submit(&mut self, command: Command) -> Result<(),String>{
+    self.record(&command)?;
+    let result = self.apply(command);
+    self.record_outcome(&result);
+    result
+ }
+ // Applies a journaled command at the time it was first applied, returning what it produced
+ pub fn replay_command(&mut self, timestamp: DateTime<Utc>, command: Command) -> Outcome{
+    self.clock = timestamp;
+    self.outcome = Outcome::default();
+    let result = self.apply(command);
+    let mut outcome = std::mem::take(&mut self.outcome);
+    outcome.error = result.err();
+    outcome
+ }
+ fn apply(&mut self, command: Command) -> Result<(),String>{
+    match command{
+        Command::OpenMarket{ instrument, lot_size } => {
+            self.orderbooks.insert(instrument.clone(), OrderBook::with_lot_size(lot_size));
+            self.triggers.insert(instrument.clone(), TriggerBook::new());
+            println!("Opening new orderbook for market {:?} with lot size {}", instrument.to_string(), lot_size);
+            Ok(())
+        }
+        Command::PlaceMarket{ instrument, mut order } => self.market_order(instrument, &mut order).map(|_| ()),
+        Command::PlaceLimit{ instrument, price, order } => self.limit_order(instrument, price, order).map(|_| ()),
+        Command::PlaceStop{ instrument, stop_price, limit_price, order } => {
+            self.stop_order(instrument, stop_price, limit_price, order).map(|_| ())
+        }
+        Command::Cancel{ instrument, order_id } => self.cancel(instrument, order_id).map(|_| ()),
+        Command::Modify{ instrument, order_id, price, size } => self.modify(instrument, order_id, price, size).map(|_| ()),
+        Command::SessionClose => {
+            self.session_close();
             Ok(())
         }
+        Command::RestoreLimit{ instrument, price, order } => match self.orderbooks.get_mut(&instrument){
+            Some(orderbook) => {
+                orderbook.rest_order(price, order);
+                self.publish_market_data(&instrument, &[]);
+                Ok(())
+            }
+            None => {
+                Err(format!("The order book for the given instrument ({})does not exist",instrument))
+            }
+        },
+        Command::RestoreStop{ instrument, stop_price, limit_price, order } => match self.triggers.get_mut(&instrument){
+            Some(triggers) => {
+                triggers.add_stop_order(StopOrder { order, stop_price, limit_price });
+                Ok(())
+            }
+            None => {
+                Err(format!("The order book for the given instrument ({})does not exist",instrument))
+            }
+        },
+        Command::RestoreLastTradePrice{ instrument, price } => match self.triggers.get_mut(&instrument){
+            Some(triggers) => {
+                triggers.set_last_trade_price(price);
+                Ok(())
+            }
+            None => {
+                Err(format!("The order book for the given instrument ({})does not exist",instrument))
+            }
+        },
+    }
+ }
+ fn market_order(&mut self, instrument: InstrumentId, order: &mut Order) -> Result<Execution,String>{
+    match self.orderbooks.get_mut(&instrument){
+        Some(orderbook) => {
+            let fills = orderbook.fill_market_order(order);
+
+            Ok(self.finish_cycle(&instrument, Execution::from_fills(fills)))
+        }
         None => {
-            Err(format!("The order book for the given trading pair ({})does not exist",pair.to_string()))
+            Err(format!("The order book for the given instrument ({})does not exist",instrument))
+        }
+    }
+ }
+ fn limit_order(&mut self, instrument: InstrumentId, price:Decimal, order:Order) -> Result<Execution,String>{
+    match self.orderbooks.get_mut(&instrument){
+        Some(orderbook) => {
+            if orderbook.contains_order(order.id()){
+                return Err(format!("Order {} is already resting on the book", order.id()));
+            }
+            let fills = orderbook.add_limit_order(price,order);
+
+            Ok(self.finish_cycle(&instrument, Execution::from_fills(fills)))
+        }
+        None => {
+            Err(format!("The order book for the given instrument ({})does not exist",instrument))
+        }
+    }
+ }
+ fn stop_order(&mut self, instrument: InstrumentId, stop_price: Decimal, limit_price: Option<Decimal>, order: Order) -> Result<Execution,String>{
+    let Some(triggers) = self.triggers.get_mut(&instrument) else {
+        return Err(format!("The order book for the given instrument ({})does not exist",instrument));
+    };
+    if triggers.contains_order(order.id()){
+        return Err(format!("Order {} is already waiting for its trigger", order.id()));
+    }
+
+    let stop = StopOrder { order, stop_price, limit_price };
+    if !triggers.is_triggered(stop.order.bid_or_ask(), stop_price){
+        triggers.add_stop_order(stop);
+        return Ok(Execution::default());
+    }
+
+    let mut execution = Execution::default();
+    self.inject_stop(&instrument, stop, &mut execution);
+    Ok(self.finish_cycle(&instrument, execution))
+ }
+ fn inject_stop(&mut self, instrument: &InstrumentId, stop: StopOrder, execution: &mut Execution){
+    let Some(orderbook) = self.orderbooks.get_mut(instrument) else {
+        return;
+    };
+    let order_id = stop.order.id();
+    let mut order = stop.order;
+    let (fills, resting) = match stop.limit_price {
+        Some(limit_price) => {
+            let fills = orderbook.add_limit_order(limit_price, order);
+            (fills, orderbook.contains_order(order_id))
+        }
+        None => (orderbook.fill_market_order(&mut order), false),
+    };
+
+    execution.fills.extend(fills);
+    execution.triggered.push(TriggeredStop { order_id, resting });
+ }
+ // Feeds every trade of the cycle to the trigger book, injecting whatever it fires. Fills
+ // of triggered stops are appended and checked in turn, so cascades resolve in one cycle.
+ // Fills carry the engine time of the command, which keeps replays identical.
+ fn finish_cycle(&mut self, instrument: &InstrumentId, mut execution: Execution) -> Execution{
+    let mut next = 0;
+    while next < execution.fills.len(){
+        let price = execution.fills[next].price;
+        next += 1;
+
+        let triggered = match self.triggers.get_mut(instrument){
+            Some(triggers) => triggers.take_triggered(price),
+            None => Vec::new(),
+        };
+        for stop in triggered{
+            self.inject_stop(instrument, stop, &mut execution);
         }
     }
 
-   
+    for fill in &mut execution.fills{
+        fill.timestamp = self.clock;
+    }
+    self.outcome.fills.extend(execution.fills.iter().cloned());
+    self.outcome.triggered.extend(execution.triggered.iter().cloned());
+    self.publish(instrument, &execution.fills);
+    self.publish_market_data(instrument, &execution.fills);
+    execution
  }
-}
\ No newline at end of file
+ fn cancel(&mut self, instrument: InstrumentId, order_id: i32) -> Result<Order,String>{
+    match self.orderbooks.get_mut(&instrument){
+        Some(orderbook) => {
+            let order = orderbook
+                .cancel_order(order_id)
+                .or_else(|| {
+                    self.triggers
+                        .get_mut(&instrument)
+                        .and_then(|triggers| triggers.cancel_order(order_id))
+                        .map(|stop| stop.order)
+                })
+                .ok_or(format!("Order {} is not resting on the book", order_id))?;
+
+            self.publish_market_data(&instrument, &[]);
+            Ok(order)
+        }
+        None => {
+            Err(format!("The order book for the given instrument ({})does not exist",instrument))
+        }
+    }
+ }
+ fn modify(&mut self, instrument: InstrumentId, order_id: i32, price: Decimal, size: Decimal) -> Result<Execution,String>{
+    match self.orderbooks.get_mut(&instrument){
+        Some(orderbook) => {
+            let fills = orderbook
+                .modify_order(order_id, price, size)
+                .ok_or(format!("Order {} is not resting on the book", order_id))?;
+
+            Ok(self.finish_cycle(&instrument, Execution::from_fills(fills)))
+        }
+        None => {
+            Err(format!("The order book for the given instrument ({})does not exist",instrument))
+        }
+    }
+ }
+ // Markets are visited in a fixed order so the outcome is the same on every replay
+ fn session_close(&mut self) -> Vec<(InstrumentId,Order)>{
+    let mut instruments: Vec<InstrumentId> = self.orderbooks.keys().cloned().collect();
+    instruments.sort();
+
+    let mut expired = Vec::new();
+    for instrument in &instruments{
+        if let Some(orderbook) = self.orderbooks.get_mut(instrument){
+            for order in orderbook.expire_day_orders(){
+                expired.push((instrument.clone(), order));
+            }
+        }
+        if let Some(triggers) = self.triggers.get_mut(instrument){
+            for stop in triggers.expire_day_orders(){
+                expired.push((instrument.clone(), stop.order));
+            }
+        }
+        self.publish_market_data(instrument, &[]);
+    }
+    expired.sort_by(|(a_instrument, a), (b_instrument, b)| a_instrument.cmp(b_instrument).then(a.id().cmp(&b.id())));
+    expired
+ }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use super::super::orderbook::BidOrAsk;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn engine_publishes_fills_to_subscribers(){
+        let mut engine = MatchingEngine::new();
+        let instrument = InstrumentId::spot("BTC", "USD");
+        engine.add_new_market(instrument.clone());
+        let mut fills = engine.subscribe();
+
+        engine.place_limit_order(instrument.clone(), dec!(100), Order::with_id(1, 11, BidOrAsk::Ask, dec!(2))).unwrap();
+        let mut market_order = Order::with_id(2, 12, BidOrAsk::Bid, dec!(1));
+        engine.place_market_order(instrument.clone(), &mut market_order).unwrap();
+
+        let event = fills.try_recv().unwrap();
+        assert_eq!(event.instrument, instrument);
+        assert_eq!(event.fill.maker_order_id, 1);
+        assert_eq!(event.fill.taker_order_id, 2);
+        assert_eq!(event.fill.size, dec!(1));
+        assert!(fills.try_recv().is_err());
+    }
+
+    #[test]
+    fn engine_cancels_and_modifies_resting_orders(){
+        let mut engine = MatchingEngine::new();
+        let instrument = InstrumentId::spot("BTC", "USD");
+        engine.add_new_market(instrument.clone());
+
+        engine.place_limit_order(instrument.clone(), dec!(100), Order::with_id(1, 11, BidOrAsk::Bid, dec!(2))).unwrap();
+        assert!(engine.place_limit_order(instrument.clone(), dec!(99), Order::with_id(1, 11, BidOrAsk::Bid, dec!(1))).is_err());
+
+        assert!(engine.modify_order(instrument.clone(), 1, dec!(100), dec!(1)).unwrap().fills.is_empty());
+        let cancelled = engine.cancel_order(instrument.clone(), 1).unwrap();
+        assert_eq!(cancelled.size(), dec!(1));
+
+        assert!(engine.cancel_order(instrument.clone(), 1).is_err());
+        assert!(engine.cancel_order(InstrumentId::spot("ETH", "USD"), 1).is_err());
+    }
+
+    #[test]
+    fn stops_cascade_within_one_cycle(){
+        let mut engine = MatchingEngine::new();
+        let instrument = InstrumentId::spot("BTC", "USD");
+        engine.add_new_market(instrument.clone());
+
+        engine.place_limit_order(instrument.clone(), dec!(100), Order::with_id(1, 11, BidOrAsk::Ask, dec!(1))).unwrap();
+        engine.place_limit_order(instrument.clone(), dec!(101), Order::with_id(2, 12, BidOrAsk::Ask, dec!(1))).unwrap();
+        engine.place_limit_order(instrument.clone(), dec!(102), Order::with_id(3, 13, BidOrAsk::Ask, dec!(1))).unwrap();
+
+        // A stop-market at 100 lifts 101, which fires the stop-limit at 101 into 102
+        engine.place_stop_order(instrument.clone(), dec!(100), None, Order::with_id(4, 14, BidOrAsk::Bid, dec!(1))).unwrap();
+        engine.place_stop_order(instrument.clone(), dec!(101), Some(dec!(103)), Order::with_id(5, 15, BidOrAsk::Bid, dec!(2))).unwrap();
+
+        let mut market_order = Order::with_id(6, 16, BidOrAsk::Bid, dec!(1));
+        let execution = engine.place_market_order(instrument.clone(), &mut market_order).unwrap();
+
+        let takers: Vec<(i32, Decimal)> = execution.fills.iter().map(|fill|(fill.taker_order_id, fill.price)).collect();
+        assert_eq!(takers, vec![(6, dec!(100)), (4, dec!(101)), (5, dec!(102))]);
+        assert_eq!(execution.triggered, vec![
+            TriggeredStop { order_id: 4, resting: false },
+            TriggeredStop { order_id: 5, resting: true },
+        ]);
+
+        // A stop already through the last trade price fires on entry
+        let execution = engine.place_stop_order(instrument.clone(), dec!(105), None, Order::with_id(7, 17, BidOrAsk::Ask, dec!(1))).unwrap();
+        assert_eq!(execution.fills.first().unwrap().maker_order_id, 5);
+
+        engine.place_stop_order(instrument.clone(), dec!(200), None, Order::with_id(8, 18, BidOrAsk::Bid, dec!(1))).unwrap();
+        assert_eq!(engine.cancel_order(instrument.clone(), 8).unwrap().id(), 8);
+    }
+
+    #[test]
+    fn market_data_follows_the_snapshot_in_sequence(){
+        let mut engine = MatchingEngine::new();
+        let instrument = InstrumentId::spot("BTC", "USD");
+        engine.add_new_market(instrument.clone());
+        engine.place_limit_order(instrument.clone(), dec!(100), Order::with_id(1, 11, BidOrAsk::Ask, dec!(2))).unwrap();
+        engine.place_limit_order(instrument.clone(), dec!(99), Order::with_id(2, 12, BidOrAsk::Bid, dec!(1))).unwrap();
+
+        let mut events = engine.subscribe_market_data();
+        let (sequence, snapshot) = engine.snapshot(&instrument, 10).unwrap();
+        assert_eq!(sequence, 2);
+        assert_eq!(snapshot.mid_price, Some(dec!(99.5)));
+
+        let mut market_order = Order::with_id(3, 13, BidOrAsk::Bid, dec!(1));
+        engine.place_market_order(instrument.clone(), &mut market_order).unwrap();
+        engine.cancel_order(instrument.clone(), 2).unwrap();
+
+        let mut sequences = Vec::new();
+        let mut levels = Vec::new();
+        while let Ok(event) = events.try_recv(){
+            sequences.push(event.sequence);
+            match event.update {
+                MarketUpdate::Trade(fill) => assert_eq!(fill.maker_order_id, 1),
+                MarketUpdate::Ticker { price, .. } => assert_eq!(price, dec!(100)),
+                MarketUpdate::Levels { bids, asks } => levels.extend(
+                    bids.iter().chain(&asks).map(|level| (level.price, level.quantity, level.order_count))
+                ),
+            }
+        }
+        assert_eq!(sequences, vec![3, 4, 5, 6]);
+        assert_eq!(levels, vec![(dec!(100), dec!(1), 1), (dec!(99), dec!(0), 0)]);
+    }
+
+    #[test]
+    fn restored_orders_keep_their_priority(){
+        let mut engine = MatchingEngine::new();
+        let instrument = InstrumentId::spot("BTC", "USD");
+        engine.add_new_market(instrument.clone());
+
+        engine.restore_limit_order(&instrument, dec!(100), Order::with_id(1, 11, BidOrAsk::Ask, dec!(1))).unwrap();
+        engine.restore_limit_order(&instrument, dec!(100), Order::with_id(2, 12, BidOrAsk::Ask, dec!(1))).unwrap();
+        engine.restore_stop_order(&instrument, dec!(100), None, Order::with_id(3, 13, BidOrAsk::Bid, dec!(1))).unwrap();
+        engine.restore_last_trade_price(&instrument, dec!(99)).unwrap();
+
+        // Trading at 100 lifts the first restored ask, which fires the restored stop into the second
+        let mut market_order = Order::with_id(4, 14, BidOrAsk::Bid, dec!(1));
+        let execution = engine.place_market_order(instrument.clone(), &mut market_order).unwrap();
+        let makers: Vec<i32> = execution.fills.iter().map(|fill| fill.maker_order_id).collect();
+        assert_eq!(makers, vec![1, 2]);
+        assert_eq!(execution.triggered, vec![TriggeredStop { order_id: 3, resting: false }]);
+    }
+}
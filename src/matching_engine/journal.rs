@@ -0,0 +1,292 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use super::engine::{Execution, MatchingEngine, TriggeredStop};
+use crate::models::InstrumentId;
+use super::orderbook::{BidOrAsk, Fill, Order};
+
+// Every command that changes the engine. Commands are journaled before they are applied,
+// so replaying them in order into a fresh engine reproduces it exactly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    OpenMarket { instrument: InstrumentId, lot_size: Decimal },
+    PlaceMarket { instrument: InstrumentId, order: Order },
+    PlaceLimit { instrument: InstrumentId, price: Decimal, order: Order },
+    PlaceStop { instrument: InstrumentId, stop_price: Decimal, limit_price: Option<Decimal>, order: Order },
+    Cancel { instrument: InstrumentId, order_id: i32 },
+    Modify { instrument: InstrumentId, order_id: i32, price: Decimal, size: Decimal },
+    // The daily session close, which expires DAY orders in every market
+    SessionClose,
+    // State loaded from the database at startup
+    RestoreLimit { instrument: InstrumentId, price: Decimal, order: Order },
+    RestoreStop { instrument: InstrumentId, stop_price: Decimal, limit_price: Option<Decimal>, order: Order },
+    RestoreLastTradePrice { instrument: InstrumentId, price: Decimal },
+}
+
+impl Command {
+    // The order a placement command hands to the engine
+    pub fn placed_order_id(&self) -> Option<i32> {
+        match self {
+            Command::PlaceMarket { order, .. } | Command::PlaceLimit { order, .. } | Command::PlaceStop { order, .. } => Some(order.id()),
+            _ => None,
+        }
+    }
+}
+
+// What applying a command produced: its fills and the book levels it changed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Outcome {
+    pub fills: Vec<Fill>,
+    // Stop orders the command set off
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggered: Vec<TriggeredStop>,
+    pub levels: Vec<LevelChange>,
+    pub error: Option<String>,
+}
+
+// A level after the change; an order count of zero means the level is gone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelChange {
+    pub instrument: InstrumentId,
+    pub side: BidOrAsk,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub order_count: usize,
+}
+
+// A command is followed by the outcome of applying it, unless the process died in between
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalRecord {
+    Command { timestamp: DateTime<Utc>, command: Command },
+    Outcome { command_sequence: u64, outcome: Outcome },
+}
+
+// Append-only file of sequenced records, one per line: `<sequence> <crc32> <json>`, where
+// the checksum covers `<sequence> <json>`. Each run of the server starts a new journal.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    last_sequence: u64,
+}
+
+impl Journal {
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("engine-{}.journal", Utc::now().format("%Y%m%dT%H%M%S%.9fZ")));
+        let file = OpenOptions::new().append(true).create_new(true).open(&path)?;
+        Ok(Journal { path, file, last_sequence: 0 })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    // Commands are synced to disk before they are applied; outcomes only need to be flushed
+    // since a lost outcome is recomputed by replaying its command
+    pub fn append(&mut self, record: &JournalRecord) -> io::Result<u64> {
+        let sequence = self.last_sequence + 1;
+        let json = serde_json::to_string(record)?;
+        writeln!(self.file, "{} {:08x} {}", sequence, checksum(sequence, &json), json)?;
+        if matches!(record, JournalRecord::Command { .. }) {
+            self.file.sync_data()?;
+        }
+        self.last_sequence = sequence;
+        Ok(sequence)
+    }
+}
+
+fn checksum(sequence: u64, json: &str) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(sequence.to_string().as_bytes());
+    hasher.update(b" ");
+    hasher.update(json.as_bytes());
+    hasher.finalize()
+}
+
+// Reads a journal, checking every checksum and that sequences have no gaps. An incomplete
+// last line is a write torn by a crash and is dropped; any other damage is an error.
+pub fn read_journal(path: &Path) -> Result<Vec<(u64, JournalRecord)>, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        let expected = records.len() as u64 + 1;
+        let Some(content) = line.strip_suffix('\n') else {
+            eprintln!("Dropping torn record {} at the end of {}", expected, path.display());
+            break;
+        };
+
+        let mut parts = content.splitn(3, ' ');
+        let (Some(sequence), Some(crc), Some(json)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(format!("Record {} is malformed", expected));
+        };
+        let sequence: u64 = sequence.parse().map_err(|_| format!("Record {} has a bad sequence number", expected))?;
+        if sequence != expected {
+            return Err(format!("Expected record {} but found {}", expected, sequence));
+        }
+        let crc = u32::from_str_radix(crc, 16).map_err(|_| format!("Record {} has a bad checksum", sequence))?;
+        if crc != checksum(sequence, json) {
+            return Err(format!("Record {} fails its checksum", sequence));
+        }
+        let record = serde_json::from_str(json).map_err(|e| format!("Record {} cannot be parsed: {}", sequence, e))?;
+        records.push((sequence, record));
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub commands: usize,
+    pub fills: usize,
+    // Commands whose replayed outcome differs from the journaled one
+    pub mismatches: Vec<u64>,
+    // Commands journaled without an outcome, which could not be checked
+    pub unchecked: Vec<u64>,
+    // What matching each order placed by the replayed commands produced, in journal order
+    pub placed: Vec<(i32, Execution)>,
+}
+
+impl ReplayReport {
+    pub fn is_identical(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+// Re-runs the commands of a journal into `engine`, which should be fresh, and compares the
+// serialized outcome of each with the one the journal recorded
+pub fn replay(engine: &mut MatchingEngine, records: Vec<(u64, JournalRecord)>) -> ReplayReport {
+    let mut report = ReplayReport::default();
+    let mut pending: Option<(u64, String)> = None;
+
+    for (sequence, record) in records {
+        match record {
+            JournalRecord::Command { timestamp, command } => {
+                if let Some((command_sequence, _)) = pending.take() {
+                    report.unchecked.push(command_sequence);
+                }
+                let placed = command.placed_order_id();
+                let outcome = engine.replay_command(timestamp, command);
+                if let (Some(order_id), None) = (placed, &outcome.error) {
+                    let execution = Execution { fills: outcome.fills.clone(), triggered: outcome.triggered.clone() };
+                    report.placed.push((order_id, execution));
+                }
+                report.commands += 1;
+                report.fills += outcome.fills.len();
+                pending = Some((sequence, serde_json::to_string(&outcome).unwrap_or_default()));
+            }
+            JournalRecord::Outcome { command_sequence, outcome } => {
+                let recorded = serde_json::to_string(&outcome).unwrap_or_default();
+                match pending.take() {
+                    Some((replayed_sequence, replayed)) if replayed_sequence == command_sequence => {
+                        if replayed != recorded {
+                            report.mismatches.push(command_sequence);
+                        }
+                    }
+                    other => {
+                        report.mismatches.push(command_sequence);
+                        pending = other;
+                    }
+                }
+            }
+        }
+    }
+    if let Some((command_sequence, _)) = pending {
+        report.unchecked.push(command_sequence);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn journal_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("journal-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn run_session(engine: &mut MatchingEngine) {
+        let instrument = InstrumentId::spot("BTC", "USD");
+        engine.add_new_market(instrument.clone());
+        engine.place_limit_order(instrument.clone(), dec!(100), Order::with_id(1, 11, BidOrAsk::Ask, dec!(2))).unwrap();
+        engine.place_limit_order(instrument.clone(), dec!(101), Order::with_id(2, 12, BidOrAsk::Ask, dec!(1))).unwrap();
+        engine.place_stop_order(instrument.clone(), dec!(101), None, Order::with_id(3, 13, BidOrAsk::Bid, dec!(1))).unwrap();
+        engine.modify_order(instrument.clone(), 2, dec!(102), dec!(1)).unwrap();
+        let mut market_order = Order::with_id(4, 14, BidOrAsk::Bid, dec!(2.5));
+        engine.place_market_order(instrument.clone(), &mut market_order).unwrap();
+        let _ = engine.cancel_order(instrument.clone(), 99);
+        engine.expire_day_orders();
+    }
+
+    #[test]
+    fn a_journal_replays_to_identical_outcomes() {
+        let dir = journal_dir("replay");
+        let journal = Journal::create(&dir).unwrap();
+        let path = journal.path().to_path_buf();
+        let mut engine = MatchingEngine::with_journal(journal);
+        run_session(&mut engine);
+
+        let records = read_journal(&path).unwrap();
+        assert_eq!(records.len(), 16);
+
+        let mut replayed = MatchingEngine::new();
+        let report = replay(&mut replayed, records);
+        assert_eq!(report.commands, 8);
+        assert_eq!(report.fills, 3);
+        assert!(report.is_identical());
+        assert!(report.unchecked.is_empty());
+
+        // The market order's cycle set off the stop, which took what was left at 102
+        let placed: Vec<i32> = report.placed.iter().map(|(order_id, _)| *order_id).collect();
+        assert_eq!(placed, vec![1, 2, 3, 4]);
+        let (_, execution) = &report.placed[3];
+        assert_eq!(execution.fills.len(), 3);
+        assert_eq!(execution.triggered, vec![TriggeredStop { order_id: 3, resting: false }]);
+
+        let instrument = InstrumentId::spot("BTC", "USD");
+        let book = |engine: &MatchingEngine| {
+            let (sequence, mut snapshot) = engine.snapshot(&instrument, usize::MAX).unwrap();
+            snapshot.timestamp = DateTime::default();
+            (sequence, serde_json::to_string(&snapshot).unwrap())
+        };
+        assert_eq!(book(&engine), book(&replayed));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn damaged_records_are_detected() {
+        let dir = journal_dir("damage");
+        let journal = Journal::create(&dir).unwrap();
+        let path = journal.path().to_path_buf();
+        let mut engine = MatchingEngine::with_journal(journal);
+        run_session(&mut engine);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.replacen("\"price\":\"100\"", "\"price\":\"10\"", 1)).unwrap();
+        assert!(read_journal(&path).unwrap_err().contains("fails its checksum"));
+
+        // A torn final write is dropped rather than treated as damage
+        fs::write(&path, &contents[..contents.len() - 5]).unwrap();
+        assert_eq!(read_journal(&path).unwrap().len(), 15);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
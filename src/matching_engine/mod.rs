@@ -1,2 +1,6 @@
 pub mod orderbook;
-pub mod engine;
\ No newline at end of file
+pub mod engine;
+pub mod trigger_book;
+pub mod journal;
+pub mod snapshot;
+pub mod sequencer;
@@ -1,159 +1,661 @@
 #![allow(dead_code)]
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use rust_decimal::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::models::{InstrumentId, OrderSide, TimeInForce, Trade};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum BidOrAsk {
     Bid,
     Ask,
 }
-#[derive(Debug)]
+
+// A single match between the incoming (taker) order and one resting (maker) order.
+// Fills always execute at the maker's price.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fill {
+    pub maker_order_id: i32,
+    pub maker_user_id: i32,
+    pub taker_order_id: i32,
+    pub taker_user_id: i32,
+    pub price: Decimal,
+    pub size: Decimal,
+    pub aggressor: BidOrAsk,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Fill {
+    pub fn buy_order_id(&self) -> i32 {
+        match self.aggressor {
+            BidOrAsk::Bid => self.taker_order_id,
+            BidOrAsk::Ask => self.maker_order_id,
+        }
+    }
+
+    pub fn sell_order_id(&self) -> i32 {
+        match self.aggressor {
+            BidOrAsk::Bid => self.maker_order_id,
+            BidOrAsk::Ask => self.taker_order_id,
+        }
+    }
+
+    pub fn buyer_user_id(&self) -> i32 {
+        match self.aggressor {
+            BidOrAsk::Bid => self.taker_user_id,
+            BidOrAsk::Ask => self.maker_user_id,
+        }
+    }
+
+    pub fn seller_user_id(&self) -> i32 {
+        match self.aggressor {
+            BidOrAsk::Bid => self.maker_user_id,
+            BidOrAsk::Ask => self.taker_user_id,
+        }
+    }
+
+    pub fn aggressor_side(&self) -> OrderSide {
+        self.aggressor.into()
+    }
+
+    // Trade ids are assigned when the fill is persisted
+    pub fn to_trade(&self, trade_id: i32, symbol: &InstrumentId) -> Trade {
+        Trade {
+            trade_id,
+            symbol: symbol.clone(),
+            price: self.price,
+            quantity: self.size,
+            buy_order_id: self.buy_order_id(),
+            sell_order_id: self.sell_order_id(),
+            buyer_user_id: self.buyer_user_id(),
+            seller_user_id: self.seller_user_id(),
+            aggressor_side: self.aggressor_side(),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+// Smallest quantity increment representable by the DECIMAL(18, 8) columns
+pub const DEFAULT_LOT_SIZE: Decimal = Decimal::from_parts(1, 0, 0, false, 8);
+
+// Price levels are kept sorted by the BTreeMaps so matching walks them in priority
+// order without collecting and sorting. The best price on each side is cached so
+// reading the top of book is O(1); it is refreshed whenever a level is added or removed.
+// Every order entering the book is rounded down to a multiple of the lot size, so fills
+// are always whole lots and partially filled levels never keep dust around.
+// Resting orders are indexed by id so they can be cancelled or amended without a search.
+// Time in force is applied on entry: IOC and FOK orders never rest, and DAY orders are
+// remembered so they can be expired together at the session close. Levels touched since
+// the last call to `take_changed_levels` are tracked for incremental market data.
+#[derive(Debug, Clone)]
 pub struct OrderBook {
-    asks: HashMap<Decimal, Limit>,
-    bids: HashMap<Decimal, Limit>,
+    asks: BTreeMap<Decimal, Limit>,
+    bids: BTreeMap<Decimal, Limit>,
+    best_ask: Option<Decimal>,
+    best_bid: Option<Decimal>,
+    lot_size: Decimal,
+    index: HashMap<i32, OrderLocation>,
+    day_orders: HashSet<i32>,
+    changed_levels: HashSet<(BidOrAsk, Decimal)>,
+}
+
+// Where a resting order lives: its side, its price level and its slot in that level's queue
+#[derive(Debug, Clone, Copy)]
+struct OrderLocation {
+    side: BidOrAsk,
+    price: Decimal,
+    seq: u64,
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OrderBook {
     pub fn new() -> Self {
+        Self::with_lot_size(DEFAULT_LOT_SIZE)
+    }
+
+    pub fn with_lot_size(lot_size: Decimal) -> Self {
+        assert!(lot_size > Decimal::ZERO, "lot size must be positive");
         OrderBook {
-            asks: HashMap::new(),
-            bids: HashMap::new(),
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            best_ask: None,
+            best_bid: None,
+            lot_size,
+            index: HashMap::new(),
+            day_orders: HashSet::new(),
+            changed_levels: HashSet::new(),
         }
     }
 
-    pub fn fill_market_order(&mut self,market_order:&mut Order){
+    pub fn lot_size(&self) -> Decimal {
+        self.lot_size
+    }
 
-        let limits = match market_order.bid_or_ask {
-            BidOrAsk::Bid => self.ask_limits(),
-            BidOrAsk::Ask => self.bid_limits(),
-            
-        } ;
+    pub fn round_to_lot(&self, size: Decimal) -> Decimal {
+        ((size / self.lot_size).floor() * self.lot_size).normalize()
+    }
 
-         for limit_order in limits{
-                    limit_order.fill_order(market_order);
+    pub fn fill_market_order(&mut self,market_order:&mut Order) -> Vec<Fill>{
+        market_order.size = self.round_to_lot(market_order.size);
+        if !self.can_fill(market_order, None) {
+            return Vec::new();
+        }
+        self.match_order(market_order, None)
+    }
 
-                    if market_order.is_filled(){
-                        break;
-                    }
+    // Everything but FOK may trade partially. A FOK order is only let through when the
+    // opposite side holds enough size at acceptable prices to fill it completely.
+    fn can_fill(&self, order: &Order, limit_price: Option<Decimal>) -> bool {
+        if order.time_in_force != TimeInForce::FOK {
+            return true;
+        }
+        let affordable = order.budget.is_none_or(|budget| self.cost_to_fill(order.bid_or_ask, order.size) <= budget);
+        affordable && self.available_liquidity(order.bid_or_ask, limit_price, order.size) >= order.size
+    }
+
+    // Size an order on `side` could trade right now at `limit_price` or better,
+    // counting no further than `wanted`
+    pub fn available_liquidity(&self, side: BidOrAsk, limit_price: Option<Decimal>, wanted: Decimal) -> Decimal {
+        let limits: Box<dyn Iterator<Item = &Limit>> = match side {
+            BidOrAsk::Bid => Box::new(self.ask_limits()),
+            BidOrAsk::Ask => Box::new(self.bid_limits()),
+        };
+        let mut available = Decimal::ZERO;
+        for limit in limits {
+            let acceptable = match (side, limit_price) {
+                (_, None) => true,
+                (BidOrAsk::Bid, Some(limit_price)) => limit.price <= limit_price,
+                (BidOrAsk::Ask, Some(limit_price)) => limit.price >= limit_price,
+            };
+            if !acceptable || available >= wanted {
+                break;
+            }
+            available += limit.total_volume();
+        }
+        available
+    }
+
+    // Walks the opposite side best price first, stopping at `limit_price` when one is given,
+    // or once a bid's budget cannot pay for another lot. Within a level the queue is consumed
+    // in arrival order, giving price-time priority.
+    fn match_order(&mut self, order: &mut Order, limit_price: Option<Decimal>) -> Vec<Fill>{
+        let mut fills = Vec::new();
+
+        while !order.is_filled() {
+            let best_price = match order.bid_or_ask {
+                BidOrAsk::Bid => self.best_ask,
+                BidOrAsk::Ask => self.best_bid,
+            };
+            let Some(price) = best_price else {
+                break;
+            };
+            let crosses = match (order.bid_or_ask, limit_price) {
+                (_, None) => true,
+                (BidOrAsk::Bid, Some(limit_price)) => price <= limit_price,
+                (BidOrAsk::Ask, Some(limit_price)) => price >= limit_price,
+            };
+            if !crosses {
+                break;
+            }
+
+            // Only as much as the budget still pays for is offered to this level
+            let wanted = order.size;
+            if let Some(budget) = order.budget {
+                let affordable = self.round_to_lot(budget / price);
+                if affordable <= Decimal::ZERO {
+                    break;
                 }
-            
-    
+                order.size = wanted.min(affordable);
+            }
+            let offered = order.size;
+
+            let limits = match order.bid_or_ask {
+                BidOrAsk::Bid => &mut self.asks,
+                BidOrAsk::Ask => &mut self.bids,
+            };
+            let limit = limits.get_mut(&price).expect("best price always has a level");
+            let level_fills = limit.fill_order(order);
+            let filled = offered - order.size;
+            order.size = wanted - filled;
+            if let Some(budget) = &mut order.budget {
+                *budget -= filled * price;
+            }
+            self.changed_levels.insert((order.bid_or_ask.opposite(), price));
+
+            // Makers that were completely filled have left the queue
+            for fill in &level_fills {
+                let still_resting = self
+                    .index
+                    .get(&fill.maker_order_id)
+                    .is_some_and(|location| limit.contains(location.seq));
+                if !still_resting {
+                    self.index.remove(&fill.maker_order_id);
+                }
+            }
+            fills.extend(level_fills);
+
+            if limit.is_empty() {
+                self.remove_limit(order.bid_or_ask.opposite(), price);
+            }
+        }
+
+        fills
     }
 
-    //BID (BUY ORDER) => ASKS => Sorted cheapest price first
-    pub fn ask_limits(&mut self) -> Vec<&mut Limit>{
-     let mut limits = self.asks.values_mut().collect::<Vec<&mut Limit>>();
-     limits.sort_by(|a,b|a.price.cmp(&b.price));
-     limits
+    // What an order of `size` on `side` would pay (or receive) sweeping the opposite side
+    // right now. Only the size the book can absorb is priced.
+    pub fn cost_to_fill(&self, side: BidOrAsk, size: Decimal) -> Decimal {
+        let limits: Box<dyn Iterator<Item = &Limit>> = match side {
+            BidOrAsk::Bid => Box::new(self.ask_limits()),
+            BidOrAsk::Ask => Box::new(self.bid_limits()),
+        };
+        let mut remaining = size;
+        let mut cost = Decimal::ZERO;
+        for limit in limits {
+            if remaining <= Decimal::ZERO {
+                break;
+            }
+            let take = remaining.min(limit.total_volume());
+            cost += take * limit.price();
+            remaining -= take;
+        }
+        cost
+    }
 
+    //BID (BUY ORDER) => ASKS => Sorted cheapest price first
+    pub fn ask_limits(&self) -> impl Iterator<Item = &Limit> {
+        self.asks.values()
     }
 
     //ASK (SELL ORDER) => BIDS => Sorted highest price first
-    pub fn bid_limits(&mut self) -> Vec<&mut Limit>{
-     let mut limits = self.bids.values_mut().collect::<Vec<&mut Limit>>();
-        limits.sort_by(|a,b|b.price.cmp(&a.price));
-        limits
+    pub fn bid_limits(&self) -> impl Iterator<Item = &Limit> {
+        self.bids.values().rev()
     }
 
-    pub fn add_limit_order(&mut self,price:Decimal, order: Order){
-        match order.bid_or_ask {
-            BidOrAsk::Bid => {
-                 
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.best_ask
+    }
 
-                 match self.bids.get_mut(&price){
-                    Some(limit ) => {
-                        limit.add_order(order);
-                    }
-                    None => {
-                        let mut limit = Limit::new(price);
-                        limit.add_order(order);
-                        self.bids.insert(price, limit);
-                    }
-                 }
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.best_bid
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        match (self.best_bid, self.best_ask) {
+            (Some(bid), Some(ask)) => Some(ask - bid),
+            _ => None,
+        }
+    }
+
+    // A limit order priced through the opposite side trades first; only the remainder rests
+    pub fn add_limit_order(&mut self,price:Decimal, mut order: Order) -> Vec<Fill>{
+        order.size = self.round_to_lot(order.size);
+        if !self.can_fill(&order, Some(price)) {
+            return Vec::new();
+        }
+        let fills = self.match_order(&mut order, Some(price));
+        if order.is_filled() || !order.time_in_force.can_rest() {
+            return fills;
+        }
+        self.rest_order(price, order);
+        fills
+    }
+
+    // Puts an order at the back of its level without matching it. Used directly only to
+    // rebuild a book from orders that have already been matched.
+    pub fn rest_order(&mut self, price: Decimal, order: Order) {
+        if order.time_in_force == TimeInForce::DAY {
+            self.day_orders.insert(order.id);
+        }
+
+        let side = order.bid_or_ask;
+        let order_id = order.id;
+        let limits = match side {
+            BidOrAsk::Bid => &mut self.bids,
+            BidOrAsk::Ask => &mut self.asks,
+        };
+        let seq = limits
+            .entry(price)
+            .or_insert_with(|| Limit::new(price))
+            .add_order(order);
+        self.index.insert(order_id, OrderLocation { side, price, seq });
+        self.changed_levels.insert((side, price));
+
+        match side {
+            BidOrAsk::Bid if self.best_bid.is_none_or(|best| price > best) => {
+                self.best_bid = Some(price);
             }
-           
-            BidOrAsk::Ask => match self.asks.get_mut(&price){
-                Some(limit ) => {
-                    limit.add_order(order);
-                }
-                None => {
-                    let mut limit = Limit::new(price);
-                    limit.add_order(order);
-                    self.asks.insert(price, limit);
-                }
-                
+            BidOrAsk::Ask if self.best_ask.is_none_or(|best| price < best) => {
+                self.best_ask = Some(price);
+            }
+            _ => {}
+        }
+    }
+
+    // None once the level has no orders left
+    pub fn level(&self, side: BidOrAsk, price: Decimal) -> Option<&Limit> {
+        match side {
+            BidOrAsk::Bid => self.bids.get(&price),
+            BidOrAsk::Ask => self.asks.get(&price),
+        }
+    }
+
+    // Every level whose size or order count may have changed since the last call
+    pub fn take_changed_levels(&mut self) -> Vec<(BidOrAsk, Decimal)> {
+        self.changed_levels.drain().collect()
+    }
+
+    pub fn contains_order(&self, order_id: i32) -> bool {
+        self.index.contains_key(&order_id)
+    }
+
+    // Returns the resting order with its price, or None if it is not on the book
+    pub fn get_order(&self, order_id: i32) -> Option<(Decimal, &Order)> {
+        let location = self.index.get(&order_id)?;
+        let limits = match location.side {
+            BidOrAsk::Bid => &self.bids,
+            BidOrAsk::Ask => &self.asks,
+        };
+        let order = limits.get(&location.price)?.order(location.seq)?;
+        Some((location.price, order))
+    }
+
+    // Removes a resting order and returns it with whatever size was still open
+    pub fn cancel_order(&mut self, order_id: i32) -> Option<Order> {
+        let location = self.index.remove(&order_id)?;
+        let limits = match location.side {
+            BidOrAsk::Bid => &mut self.bids,
+            BidOrAsk::Ask => &mut self.asks,
+        };
+        let limit = limits.get_mut(&location.price)?;
+        let order = limit.cancel_order(location.seq);
+        self.changed_levels.insert((location.side, location.price));
+
+        if limit.is_empty() {
+            self.remove_limit(location.side, location.price);
+        }
+        order
+    }
+
+    // Cancels every DAY order still resting, returning what was removed
+    pub fn expire_day_orders(&mut self) -> Vec<Order> {
+        let day_orders: Vec<i32> = self.day_orders.drain().collect();
+        day_orders
+            .into_iter()
+            .filter_map(|order_id| self.cancel_order(order_id))
+            .collect()
+    }
+
+    // Reducing the size at the same price keeps the order's place in the queue. Any other
+    // change is a cancel/replace: the order goes to the back of its new level and may trade
+    // immediately if the new price crosses the spread. A size of zero cancels the order.
+    pub fn modify_order(&mut self, order_id: i32, price: Decimal, size: Decimal) -> Option<Vec<Fill>> {
+        let location = *self.index.get(&order_id)?;
+        let size = self.round_to_lot(size);
+
+        if price == location.price && size > Decimal::ZERO {
+            let limits = match location.side {
+                BidOrAsk::Bid => &mut self.bids,
+                BidOrAsk::Ask => &mut self.asks,
+            };
+            let order = limits.get_mut(&location.price)?.order_mut(location.seq)?;
+            if size <= order.size {
+                order.size = size;
+                self.changed_levels.insert((location.side, location.price));
+                return Some(Vec::new());
+            }
+        }
+
+        let mut order = self.cancel_order(order_id)?;
+        if size <= Decimal::ZERO {
+            return Some(Vec::new());
+        }
+        order.size = size;
+        Some(self.add_limit_order(price, order))
+    }
+
+    fn remove_limit(&mut self, side: BidOrAsk, price: Decimal) {
+        match side {
+            BidOrAsk::Bid => {
+                self.bids.remove(&price);
+                self.best_bid = self.bids.keys().next_back().copied();
+            }
+            BidOrAsk::Ask => {
+                self.asks.remove(&price);
+                self.best_ask = self.asks.keys().next().copied();
             }
+        }
+    }
+}
+
+impl BidOrAsk {
+    pub fn opposite(&self) -> BidOrAsk {
+        match self {
+            BidOrAsk::Bid => BidOrAsk::Ask,
+            BidOrAsk::Ask => BidOrAsk::Bid,
+        }
+    }
+}
 
-            
+impl From<BidOrAsk> for OrderSide {
+    fn from(bid_or_ask: BidOrAsk) -> Self {
+        match bid_or_ask {
+            BidOrAsk::Bid => OrderSide::Buy,
+            BidOrAsk::Ask => OrderSide::Sell,
+        }
+    }
+}
 
+impl From<&OrderSide> for BidOrAsk {
+    fn from(side: &OrderSide) -> Self {
+        match side {
+            OrderSide::Buy => BidOrAsk::Bid,
+            OrderSide::Sell => BidOrAsk::Ask,
         }
     }
 }
 
 
-#[derive(Debug)]
+// Orders queue in arrival order. Each order is addressed by a sequence number that maps
+// straight to its slot, so cancels are O(1): the slot is zeroed in place and skipped
+// (then dropped) once it reaches the front of the queue.
+#[derive(Debug, Clone)]
 pub struct Limit {
     price: Decimal,
-    orders:Vec<Order>,
+    orders:VecDeque<Order>,
+    front_seq: u64,
+    live_orders: usize,
     }
 
     impl Limit {
         pub fn new(price:Decimal) -> Limit {
             Limit {
                 price,
-                orders: Vec::new(),
+                orders: VecDeque::new(),
+                front_seq: 0,
+                live_orders: 0,
             }
         }
 
-        fn total_volume(&self) -> f64 {
-            let volume = self
+        pub fn price(&self) -> Decimal {
+            self.price
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.live_orders == 0
+        }
+
+        pub fn order_count(&self) -> usize {
+            self.live_orders
+        }
+
+        pub fn total_volume(&self) -> Decimal {
+            self
             .orders
             .iter()
             .map(|order|order.size)
-            .reduce(|a,b|a + b)
-            .unwrap();
+            .sum()
+        }
+
+        // Resting orders in queue priority
+        pub fn orders(&self) -> impl Iterator<Item = &Order> {
+            self.orders.iter().filter(|order|!order.is_filled())
+        }
+
+        fn slot(&self, seq: u64) -> Option<usize> {
+            let slot = usize::try_from(seq.checked_sub(self.front_seq)?).ok()?;
+            (slot < self.orders.len()).then_some(slot)
+        }
+
+        fn contains(&self, seq: u64) -> bool {
+            self.order(seq).is_some()
+        }
+
+        fn order(&self, seq: u64) -> Option<&Order> {
+            self.orders.get(self.slot(seq)?).filter(|order|!order.is_filled())
+        }
+
+        fn order_mut(&mut self, seq: u64) -> Option<&mut Order> {
+            let slot = self.slot(seq)?;
+            self.orders.get_mut(slot).filter(|order|!order.is_filled())
+        }
+
+        fn cancel_order(&mut self, seq: u64) -> Option<Order> {
+            let order = self.order_mut(seq)?;
+            let cancelled = order.clone();
+            order.size = Decimal::ZERO;
+            self.live_orders -= 1;
 
-            volume
+            while self.orders.front().is_some_and(|order|order.is_filled()) {
+                self.orders.pop_front();
+                self.front_seq += 1;
+            }
+            while self.orders.back().is_some_and(|order|order.is_filled()) {
+                self.orders.pop_back();
+            }
+            Some(cancelled)
         }
 
-        fn fill_order(&mut self, market_order: &mut Order) {
-            for limit_order in self.orders.iter_mut(){
-                match  market_order.size >= limit_order.size {
+        // Orders are consumed from the front of the queue and removed as soon as they are filled
+        fn fill_order(&mut self, market_order: &mut Order) -> Vec<Fill> {
+            let mut fills = Vec::new();
+            let timestamp = Utc::now();
+            while let Some(limit_order) = self.orders.front_mut(){
+                // A cancelled slot waiting to be dropped
+                if limit_order.is_filled(){
+                    self.orders.pop_front();
+                    self.front_seq += 1;
+                    continue;
+                }
+
+                let fill_size = match  market_order.size >= limit_order.size {
                     true => {
-                        market_order.size -= limit_order.size;
-                        limit_order.size = 0.0;
+                        let size = limit_order.size;
+                        market_order.size -= size;
+                        limit_order.size = Decimal::ZERO;
+                        size
                     },
                     false => {
-                        limit_order.size -= market_order.size;
-                        market_order.size = 0.0;
-
+                        let size = market_order.size;
+                        limit_order.size -= size;
+                        market_order.size = Decimal::ZERO;
+                        size
                     }
 
+                };
+
+                fills.push(Fill {
+                    maker_order_id: limit_order.id,
+                    maker_user_id: limit_order.user_id,
+                    taker_order_id: market_order.id,
+                    taker_user_id: market_order.user_id,
+                    price: self.price,
+                    size: fill_size,
+                    aggressor: market_order.bid_or_ask,
+                    timestamp,
+                });
+
+                if limit_order.is_filled(){
+                    self.orders.pop_front();
+                    self.front_seq += 1;
+                    self.live_orders -= 1;
                 }
 
                 if market_order.is_filled(){
                     break;
                 }
             }
+            fills
             }
 
-         fn add_order(&mut self, order:Order) {
-            self.orders.push(order);
-        } 
+         // Returns the sequence number addressing the order within this level
+         fn add_order(&mut self, order:Order) -> u64 {
+            let seq = self.front_seq + self.orders.len() as u64;
+            self.orders.push_back(order);
+            self.live_orders += 1;
+            seq
+        }
     }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Order {
-    size: f64,
+    id: i32,
+    user_id: i32,
+    size: Decimal,
     bid_or_ask: BidOrAsk,
+    time_in_force: TimeInForce,
+    // Most a buy without a limit price may pay in total, as its funds were locked up front
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    budget: Option<Decimal>,
 }
 
 impl Order {
-    pub fn new( bid_or_ask: BidOrAsk,size: f64,) -> Self {
-        Order { size, bid_or_ask }
+    pub fn new( bid_or_ask: BidOrAsk,size: Decimal,) -> Self {
+        Order { id: 0, user_id: 0, size, bid_or_ask, time_in_force: TimeInForce::GTC, budget: None }
+    }
+
+    // Orders coming from the API carry the `orders.order_id` they were persisted under
+    // and the `user_id` of their owner
+    pub fn with_id(id: i32, user_id: i32, bid_or_ask: BidOrAsk, size: Decimal) -> Self {
+        Order { id, user_id, size, bid_or_ask, time_in_force: TimeInForce::GTC, budget: None }
+    }
+
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn with_budget(mut self, budget: Decimal) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn time_in_force(&self) -> &TimeInForce {
+        &self.time_in_force
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn user_id(&self) -> i32 {
+        self.user_id
+    }
+
+    pub fn bid_or_ask(&self) -> BidOrAsk {
+        self.bid_or_ask
+    }
+
+    pub fn size(&self) -> Decimal {
+        self.size
     }
 
     pub fn is_filled(&self) -> bool {
-        self.size <= 0.0
+        self.size <= Decimal::ZERO
     }
 }
 
@@ -162,29 +664,50 @@ pub mod tests {
     use super::*;
     use rust_decimal_macros::dec;
 
+    fn summary(fills: &[Fill]) -> Vec<(i32, Decimal, Decimal)> {
+        fills.iter().map(|fill|(fill.maker_order_id, fill.price, fill.size)).collect()
+    }
+
     #[test]
     fn order_book_fill_market_order_ask(){
         let mut order_book = OrderBook::new();
 
-        order_book.add_limit_order(dec!(500),Order::new(BidOrAsk::Ask,10.0));
-        order_book.add_limit_order(dec!(100),Order::new(BidOrAsk::Ask,10.0));
-        order_book.add_limit_order(dec!(200),Order::new(BidOrAsk::Ask,10.0));
-        order_book.add_limit_order(dec!(300),Order::new(BidOrAsk::Ask,10.0));
+        order_book.add_limit_order(dec!(500),Order::new(BidOrAsk::Ask,dec!(10.0)));
+        order_book.add_limit_order(dec!(100),Order::new(BidOrAsk::Ask,dec!(10.0)));
+        order_book.add_limit_order(dec!(200),Order::new(BidOrAsk::Ask,dec!(10.0)));
+        order_book.add_limit_order(dec!(300),Order::new(BidOrAsk::Ask,dec!(10.0)));
 
-        let mut market_order = Order::new(BidOrAsk::Bid,10.0);
-        order_book.fill_market_order(&mut market_order);
+        let mut market_order = Order::new(BidOrAsk::Bid,dec!(10.0));
+        let fills = order_book.fill_market_order(&mut market_order);
+
+        assert!(market_order.is_filled());
+        assert_eq!(fills.first().unwrap().price, dec!(100));
 
-        let ask_limits = order_book.ask_limits();
-        let matched_limits = ask_limits.get(0).unwrap();
+        // The exhausted level at 100 is pruned and the next level becomes the best ask
+        let best_limit = order_book.ask_limits().next().unwrap();
+        assert_eq!(best_limit.price, dec!(200));
+        assert_eq!(order_book.best_ask(), Some(dec!(200)));
+        assert_eq!(order_book.ask_limits().count(), 3);
+    }
+
+    #[test]
+    fn order_book_sorts_and_prunes_levels(){
+        let mut order_book = OrderBook::new();
 
-        assert_eq!(market_order.is_filled(), true);
-        assert_eq!(matched_limits.price, dec!(100));
+        order_book.add_limit_order(dec!(99),Order::new(BidOrAsk::Bid,dec!(1.0)));
+        order_book.add_limit_order(dec!(101),Order::new(BidOrAsk::Bid,dec!(1.0)));
+        order_book.add_limit_order(dec!(100),Order::new(BidOrAsk::Bid,dec!(1.0)));
 
-        let matched_order = matched_limits.orders.get(0).unwrap();
-        assert_eq!(matched_order.is_filled(), true); 
+        let prices: Vec<Decimal> = order_book.bid_limits().map(|limit|limit.price()).collect();
+        assert_eq!(prices, vec![dec!(101), dec!(100), dec!(99)]);
+        assert_eq!(order_book.best_bid(), Some(dec!(101)));
 
-    
-        
+        let mut market_order = Order::new(BidOrAsk::Ask,dec!(3.0));
+        order_book.fill_market_order(&mut market_order);
+
+        assert!(market_order.is_filled());
+        assert_eq!(order_book.bid_limits().count(), 0);
+        assert_eq!(order_book.best_bid(), None);
     }
 
     #[test]
@@ -192,31 +715,31 @@ pub mod tests {
     fn limit_total_volume(){
         let price = dec!(10000.0);
         let mut limit = Limit::new(price);
-        let buy_limit_order_a = Order::new(BidOrAsk::Bid,100.0);
-        let buy_limit_order_b =  Order::new(BidOrAsk::Bid,99.0);
+        let buy_limit_order_a = Order::new(BidOrAsk::Bid,dec!(100.0));
+        let buy_limit_order_b =  Order::new(BidOrAsk::Bid,dec!(99.0));
         limit.add_order(buy_limit_order_a);
         limit.add_order(buy_limit_order_b);
 
-        assert_eq!(limit.total_volume(),199.0)
+        assert_eq!(limit.total_volume(),dec!(199.0))
 
     }
     #[test]
         fn limit_order_multi_fill(){
         let price = dec!(10000.0);
         let mut limit = Limit::new(price);
-        let buy_limit_order_a = Order::new(BidOrAsk::Bid,100.0);
-        let buy_limit_order_b = Order::new(BidOrAsk::Bid,100.0);
+        let buy_limit_order_a = Order::new(BidOrAsk::Bid,dec!(100.0));
+        let buy_limit_order_b = Order::new(BidOrAsk::Bid,dec!(100.0));
         limit.add_order(buy_limit_order_a);
         limit.add_order(buy_limit_order_b);
 
-        let mut market_sell_order = Order::new(BidOrAsk::Ask, 199.0);
+        let mut market_sell_order = Order::new(BidOrAsk::Ask, dec!(199.0));
         limit.fill_order(&mut market_sell_order);
-        
 
-        assert_eq!(market_sell_order.is_filled(),true);
-        assert_eq!(limit.orders.get(0).unwrap().is_filled(),true);
-        assert_eq!(limit.orders.get(1).unwrap().is_filled(),false);
-        assert_eq!(limit.orders.get(1).unwrap().size,1.0);
+
+        assert!(market_sell_order.is_filled());
+        assert_eq!(limit.orders.len(),1);
+        assert!(!limit.orders.front().unwrap().is_filled());
+        assert_eq!(limit.orders.front().unwrap().size,dec!(1.0));
 
         println!("{:?}",limit);
 
@@ -225,17 +748,269 @@ pub mod tests {
      fn limit_order_single_fill(){
         let price = dec!(10000.0);
         let mut limit = Limit::new(price);
-        let buy_limit_order = Order::new(BidOrAsk::Bid,100.0);
+        let buy_limit_order = Order::new(BidOrAsk::Bid,dec!(100.0));
         limit.add_order(buy_limit_order);
 
-        let mut market_sell_order = Order::new(BidOrAsk::Ask, 99.0);
+        let mut market_sell_order = Order::new(BidOrAsk::Ask, dec!(99.0));
         limit.fill_order(&mut market_sell_order);
         println!("{:?}",limit);
 
-        assert_eq!(market_sell_order.is_filled(),true);
-        assert_eq!(limit.orders.get(0).unwrap().size,1.0);
+        assert!(market_sell_order.is_filled());
+        assert_eq!(limit.orders.front().unwrap().size,dec!(1.0));
+
+     }
+
+     #[test]
+     fn fill_market_order_reports_fills(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(101),Order::with_id(1,101,BidOrAsk::Ask,dec!(5.0)));
+        order_book.add_limit_order(dec!(100),Order::with_id(2,102,BidOrAsk::Ask,dec!(5.0)));
+
+        let mut market_order = Order::with_id(3,103,BidOrAsk::Bid,dec!(7.0));
+        let fills = order_book.fill_market_order(&mut market_order);
+
+        assert_eq!(summary(&fills), vec![
+            (2, dec!(100), dec!(5.0)),
+            (1, dec!(101), dec!(2.0)),
+        ]);
 
+        // The exhausted order at 100 must not produce empty fills afterwards
+        let mut market_order = Order::with_id(4,104,BidOrAsk::Bid,dec!(1.0));
+        let fills = order_book.fill_market_order(&mut market_order);
+        assert_eq!(summary(&fills), vec![(1, dec!(101), dec!(1.0))]);
      }
 
-   
-}
\ No newline at end of file
+     #[test]
+     fn decimal_sizes_leave_no_dust(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(1,101,BidOrAsk::Ask,dec!(0.1)));
+        order_book.add_limit_order(dec!(100),Order::with_id(2,102,BidOrAsk::Ask,dec!(0.2)));
+
+        let mut market_order = Order::with_id(3,103,BidOrAsk::Bid,dec!(0.3));
+        order_book.fill_market_order(&mut market_order);
+
+        assert!(market_order.is_filled());
+        assert_eq!(order_book.ask_limits().count(), 0);
+
+        // Repeated partial fills take a level down to exactly zero
+        order_book.add_limit_order(dec!(100),Order::with_id(4,104,BidOrAsk::Ask,dec!(1)));
+        for _ in 0..3 {
+            let mut market_order = Order::new(BidOrAsk::Bid,dec!(0.3));
+            order_book.fill_market_order(&mut market_order);
+            assert!(market_order.is_filled());
+        }
+        assert_eq!(order_book.ask_limits().next().unwrap().total_volume(), dec!(0.1));
+
+        let mut market_order = Order::new(BidOrAsk::Bid,dec!(0.1));
+        order_book.fill_market_order(&mut market_order);
+        assert_eq!(order_book.ask_limits().count(), 0);
+        assert_eq!(order_book.best_ask(), None);
+     }
+
+     #[test]
+     fn sizes_are_rounded_down_to_lot_size(){
+        let mut order_book = OrderBook::with_lot_size(dec!(0.01));
+        assert_eq!(order_book.round_to_lot(dec!(1.2345)), dec!(1.23));
+
+        order_book.add_limit_order(dec!(100),Order::new(BidOrAsk::Bid,dec!(1.2345)));
+        assert_eq!(order_book.bid_limits().next().unwrap().total_volume(), dec!(1.23));
+
+        // Anything smaller than a lot never reaches the book
+        order_book.add_limit_order(dec!(99),Order::new(BidOrAsk::Bid,dec!(0.009)));
+        assert_eq!(order_book.bid_limits().count(), 1);
+
+        let mut market_order = Order::new(BidOrAsk::Ask,dec!(0.505));
+        let fills = order_book.fill_market_order(&mut market_order);
+        assert_eq!(fills.first().unwrap().size, dec!(0.5));
+        assert_eq!(order_book.bid_limits().next().unwrap().total_volume(), dec!(0.73));
+     }
+
+     #[test]
+     fn crossing_limit_order_matches_before_resting(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(1,101,BidOrAsk::Ask,dec!(1)));
+        order_book.add_limit_order(dec!(101),Order::with_id(2,102,BidOrAsk::Ask,dec!(1)));
+        order_book.add_limit_order(dec!(103),Order::with_id(3,103,BidOrAsk::Ask,dec!(1)));
+
+        let fills = order_book.add_limit_order(dec!(102),Order::with_id(4,104,BidOrAsk::Bid,dec!(3)));
+
+        // Trades at the resting prices up to the limit, never through it
+        assert_eq!(summary(&fills), vec![
+            (1, dec!(100), dec!(1)),
+            (2, dec!(101), dec!(1)),
+        ]);
+        assert_eq!(order_book.best_bid(), Some(dec!(102)));
+        assert_eq!(order_book.bid_limits().next().unwrap().total_volume(), dec!(1));
+        assert_eq!(order_book.best_ask(), Some(dec!(103)));
+     }
+
+     #[test]
+     fn crossing_limit_order_respects_time_priority(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(1,101,BidOrAsk::Bid,dec!(2)));
+        order_book.add_limit_order(dec!(100),Order::with_id(2,102,BidOrAsk::Bid,dec!(2)));
+
+        let fills = order_book.add_limit_order(dec!(99),Order::with_id(3,103,BidOrAsk::Ask,dec!(3)));
+
+        assert_eq!(summary(&fills), vec![
+            (1, dec!(100), dec!(2)),
+            (2, dec!(100), dec!(1)),
+        ]);
+        assert_eq!(order_book.best_bid(), Some(dec!(100)));
+        assert_eq!(order_book.best_ask(), None);
+
+        // A non-marketable order simply rests
+        let fills = order_book.add_limit_order(dec!(101),Order::with_id(4,104,BidOrAsk::Ask,dec!(1)));
+        assert!(fills.is_empty());
+        assert_eq!(order_book.spread(), Some(dec!(1)));
+     }
+
+     #[test]
+     fn fills_map_onto_trades(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(7,107,BidOrAsk::Bid,dec!(2)));
+
+        let fills = order_book.add_limit_order(dec!(100),Order::with_id(8,108,BidOrAsk::Ask,dec!(1.5)));
+        let fill = fills.first().unwrap();
+
+        assert_eq!(fill.taker_order_id, 8);
+        assert_eq!(fill.aggressor, BidOrAsk::Ask);
+
+        let trade = fill.to_trade(1, &InstrumentId::spot("BTC", "USD"));
+        assert_eq!(trade.buy_order_id, 7);
+        assert_eq!(trade.sell_order_id, 8);
+        assert_eq!(trade.buyer_user_id, 107);
+        assert_eq!(trade.seller_user_id, 108);
+        assert_eq!(trade.quantity, dec!(1.5));
+        assert_eq!(trade.aggressor_side.to_string(), "sell");
+        assert_eq!(trade.timestamp, fill.timestamp);
+     }
+
+     #[test]
+     fn cancel_order_removes_it_from_the_queue(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(1,101,BidOrAsk::Ask,dec!(1)));
+        order_book.add_limit_order(dec!(100),Order::with_id(2,102,BidOrAsk::Ask,dec!(2)));
+        order_book.add_limit_order(dec!(100),Order::with_id(3,103,BidOrAsk::Ask,dec!(3)));
+
+        let cancelled = order_book.cancel_order(2).unwrap();
+        assert_eq!(cancelled.size(), dec!(2));
+        assert_eq!(cancelled.user_id(), 102);
+        assert!(order_book.cancel_order(2).is_none());
+        assert!(!order_book.contains_order(2));
+
+        let limit = order_book.ask_limits().next().unwrap();
+        assert_eq!(limit.order_count(), 2);
+        assert_eq!(limit.total_volume(), dec!(4));
+
+        // The cancelled slot is skipped by matching
+        let mut market_order = Order::with_id(4,104,BidOrAsk::Bid,dec!(2));
+        let fills = order_book.fill_market_order(&mut market_order);
+        assert_eq!(summary(&fills), vec![(1, dec!(100), dec!(1)), (3, dec!(100), dec!(1))]);
+        assert!(!order_book.contains_order(1));
+        assert_eq!(order_book.get_order(3).unwrap().1.size(), dec!(2));
+
+        // Cancelling the last order prunes the level
+        order_book.cancel_order(3).unwrap();
+        assert_eq!(order_book.best_ask(), None);
+     }
+
+     #[test]
+     fn modify_order_priority(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(1,101,BidOrAsk::Bid,dec!(5)));
+        order_book.add_limit_order(dec!(100),Order::with_id(2,102,BidOrAsk::Bid,dec!(5)));
+
+        // Reducing keeps order 1 at the front
+        order_book.modify_order(1, dec!(100), dec!(2)).unwrap();
+        let mut market_order = Order::new(BidOrAsk::Ask,dec!(1));
+        let fills = order_book.fill_market_order(&mut market_order);
+        assert_eq!(summary(&fills), vec![(1, dec!(100), dec!(1))]);
+
+        // Increasing sends it to the back of the queue
+        order_book.modify_order(1, dec!(100), dec!(3)).unwrap();
+        let mut market_order = Order::new(BidOrAsk::Ask,dec!(1));
+        let fills = order_book.fill_market_order(&mut market_order);
+        assert_eq!(summary(&fills), vec![(2, dec!(100), dec!(1))]);
+
+        // Repricing through the spread trades straight away
+        order_book.add_limit_order(dec!(101),Order::with_id(3,103,BidOrAsk::Ask,dec!(1)));
+        let fills = order_book.modify_order(2, dec!(101), dec!(4)).unwrap();
+        assert_eq!(summary(&fills), vec![(3, dec!(101), dec!(1))]);
+        assert_eq!(order_book.get_order(2).unwrap(), (dec!(101), &Order::with_id(2,102,BidOrAsk::Bid,dec!(3))));
+
+        assert!(order_book.modify_order(1, dec!(100), dec!(0)).unwrap().is_empty());
+        assert!(!order_book.contains_order(1));
+        assert!(order_book.modify_order(42, dec!(100), dec!(1)).is_none());
+     }
+
+     #[test]
+     fn ioc_order_never_rests(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(1,101,BidOrAsk::Ask,dec!(1)));
+
+        let ioc = Order::with_id(2,102,BidOrAsk::Bid,dec!(3)).with_time_in_force(TimeInForce::IOC);
+        let fills = order_book.add_limit_order(dec!(100),ioc);
+
+        assert_eq!(summary(&fills), vec![(1, dec!(100), dec!(1))]);
+        assert!(!order_book.contains_order(2));
+        assert_eq!(order_book.best_bid(), None);
+     }
+
+     #[test]
+     fn fok_order_fills_completely_or_not_at_all(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(1,101,BidOrAsk::Ask,dec!(1)));
+        order_book.add_limit_order(dec!(101),Order::with_id(2,102,BidOrAsk::Ask,dec!(1)));
+        order_book.add_limit_order(dec!(102),Order::with_id(3,103,BidOrAsk::Ask,dec!(1)));
+
+        // Three lots exist, but only two at or below the limit
+        let fok = Order::with_id(4,104,BidOrAsk::Bid,dec!(3)).with_time_in_force(TimeInForce::FOK);
+        assert!(order_book.add_limit_order(dec!(101),fok).is_empty());
+        assert!(!order_book.contains_order(4));
+        assert_eq!(order_book.ask_limits().count(), 3);
+
+        let mut fok = Order::with_id(5,105,BidOrAsk::Bid,dec!(4)).with_time_in_force(TimeInForce::FOK);
+        assert!(order_book.fill_market_order(&mut fok).is_empty());
+        assert_eq!(order_book.ask_limits().count(), 3);
+
+        let fok = Order::with_id(6,106,BidOrAsk::Bid,dec!(2)).with_time_in_force(TimeInForce::FOK);
+        let fills = order_book.add_limit_order(dec!(101),fok);
+        assert_eq!(summary(&fills), vec![(1, dec!(100), dec!(1)), (2, dec!(101), dec!(1))]);
+     }
+
+     #[test]
+     fn budgeted_bid_stops_at_its_budget(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(100),Order::with_id(1,101,BidOrAsk::Ask,dec!(1)));
+        order_book.add_limit_order(dec!(200),Order::with_id(2,102,BidOrAsk::Ask,dec!(2)));
+
+        // 250 pays for the lot at 100 and 0.75 of the level at 200
+        let mut bid = Order::with_id(3,103,BidOrAsk::Bid,dec!(3)).with_budget(dec!(250));
+        let fills = order_book.fill_market_order(&mut bid);
+        assert_eq!(summary(&fills), vec![(1, dec!(100), dec!(1)), (2, dec!(200), dec!(0.75))]);
+        assert_eq!(bid.size(), dec!(1.25));
+
+        // The whole size would cost more than the budget
+        let mut fok = Order::with_id(4,104,BidOrAsk::Bid,dec!(1)).with_time_in_force(TimeInForce::FOK).with_budget(dec!(199));
+        assert!(order_book.fill_market_order(&mut fok).is_empty());
+        assert_eq!(order_book.ask_limits().next().unwrap().total_volume(), dec!(1.25));
+     }
+
+     #[test]
+     fn day_orders_expire_together(){
+        let mut order_book = OrderBook::new();
+        order_book.add_limit_order(dec!(99),Order::with_id(1,101,BidOrAsk::Bid,dec!(1)).with_time_in_force(TimeInForce::DAY));
+        order_book.add_limit_order(dec!(98),Order::with_id(2,102,BidOrAsk::Bid,dec!(1)).with_time_in_force(TimeInForce::DAY));
+        order_book.add_limit_order(dec!(97),Order::with_id(3,103,BidOrAsk::Bid,dec!(1)));
+
+        // Already gone by the close
+        order_book.cancel_order(2).unwrap();
+
+        let expired: Vec<i32> = order_book.expire_day_orders().iter().map(|order|order.id()).collect();
+        assert_eq!(expired, vec![1]);
+        assert!(order_book.contains_order(3));
+        assert_eq!(order_book.best_bid(), Some(dec!(97)));
+        assert!(order_book.expire_day_orders().is_empty());
+     }
+}
@@ -0,0 +1,196 @@
+use std::thread;
+use rust_decimal::Decimal;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use super::engine::{Execution, MarketEvent, MatchingEngine};
+use super::orderbook::{BidOrAsk, Order};
+use super::snapshot::EngineSnapshot;
+use crate::models::{InstrumentId, OrderBookSnapshot};
+
+// How many requests may wait for the engine before callers have to wait for room
+const REQUEST_QUEUE_CAPACITY: usize = 1024;
+
+pub const STOPPED: &str = "The matching engine has stopped";
+
+// What the engine thread can be asked to do; each request carries the sender of its reply
+enum EngineRequest {
+    OpenMarket { instrument: InstrumentId, lot_size: Decimal, reply: oneshot::Sender<()> },
+    PlaceMarket { instrument: InstrumentId, order: Order, reply: oneshot::Sender<Result<Execution, String>> },
+    PlaceLimit { instrument: InstrumentId, price: Decimal, order: Order, reply: oneshot::Sender<Result<Execution, String>> },
+    PlaceStop {
+        instrument: InstrumentId,
+        stop_price: Decimal,
+        limit_price: Option<Decimal>,
+        order: Order,
+        reply: oneshot::Sender<Result<Execution, String>>,
+    },
+    Cancel { instrument: InstrumentId, order_id: i32, reply: oneshot::Sender<Result<Order, String>> },
+    ExpireDayOrders { reply: oneshot::Sender<Vec<(InstrumentId, Order)>> },
+    CostToFill { instrument: InstrumentId, side: BidOrAsk, size: Decimal, reply: oneshot::Sender<Result<Decimal, String>> },
+    BookSnapshot { instrument: InstrumentId, depth: usize, reply: oneshot::Sender<Result<(u64, OrderBookSnapshot), String>> },
+    // Every market's book, all as of the same point
+    BookSnapshots { depth: usize, reply: oneshot::Sender<Vec<(InstrumentId, u64, OrderBookSnapshot)>> },
+    EngineSnapshot { reply: oneshot::Sender<EngineSnapshot> },
+}
+
+// The way to a matching engine running on a thread of its own. The thread owns the engine
+// and applies requests one at a time in the order they were queued, so matching needs no
+// lock and every market sees one strict sequence of commands. Handles are cheap to clone;
+// the thread stops once the last one is dropped.
+#[derive(Debug, Clone)]
+pub struct EngineHandle {
+    requests: mpsc::Sender<EngineRequest>,
+    market_data: broadcast::Sender<MarketEvent>,
+}
+
+impl EngineHandle {
+    pub fn spawn(mut engine: MatchingEngine) -> std::io::Result<Self> {
+        let (requests, mut queue) = mpsc::channel(REQUEST_QUEUE_CAPACITY);
+        let market_data = engine.market_data_sender();
+        thread::Builder::new().name("matching-engine".to_string()).spawn(move || {
+            while let Some(request) = queue.blocking_recv() {
+                handle_request(&mut engine, request);
+            }
+        })?;
+        Ok(EngineHandle { requests, market_data })
+    }
+
+    async fn request<T>(&self, request: impl FnOnce(oneshot::Sender<T>) -> EngineRequest) -> Result<T, String> {
+        let (reply, response) = oneshot::channel();
+        self.requests.send(request(reply)).await.map_err(|_| STOPPED.to_string())?;
+        response.await.map_err(|_| STOPPED.to_string())
+    }
+
+    // Market data of every market. Take the receiver before the snapshot it is applied to.
+    pub fn subscribe_market_data(&self) -> broadcast::Receiver<MarketEvent> {
+        self.market_data.subscribe()
+    }
+
+    // Opens a market unless it is open already
+    pub async fn open_market(&self, instrument: InstrumentId, lot_size: Decimal) -> Result<(), String> {
+        self.request(|reply| EngineRequest::OpenMarket { instrument, lot_size, reply }).await
+    }
+
+    pub async fn place_market_order(&self, instrument: InstrumentId, order: Order) -> Result<Execution, String> {
+        self.request(|reply| EngineRequest::PlaceMarket { instrument, order, reply }).await?
+    }
+
+    pub async fn place_limit_order(&self, instrument: InstrumentId, price: Decimal, order: Order) -> Result<Execution, String> {
+        self.request(|reply| EngineRequest::PlaceLimit { instrument, price, order, reply }).await?
+    }
+
+    pub async fn place_stop_order(
+        &self,
+        instrument: InstrumentId,
+        stop_price: Decimal,
+        limit_price: Option<Decimal>,
+        order: Order,
+    ) -> Result<Execution, String> {
+        self.request(|reply| EngineRequest::PlaceStop { instrument, stop_price, limit_price, order, reply }).await?
+    }
+
+    pub async fn cancel_order(&self, instrument: InstrumentId, order_id: i32) -> Result<Order, String> {
+        self.request(|reply| EngineRequest::Cancel { instrument, order_id, reply }).await?
+    }
+
+    pub async fn expire_day_orders(&self) -> Result<Vec<(InstrumentId, Order)>, String> {
+        self.request(|reply| EngineRequest::ExpireDayOrders { reply }).await
+    }
+
+    pub async fn cost_to_fill(&self, instrument: InstrumentId, side: BidOrAsk, size: Decimal) -> Result<Decimal, String> {
+        self.request(|reply| EngineRequest::CostToFill { instrument, side, size, reply }).await?
+    }
+
+    pub async fn snapshot(&self, instrument: InstrumentId, depth: usize) -> Result<(u64, OrderBookSnapshot), String> {
+        self.request(|reply| EngineRequest::BookSnapshot { instrument, depth, reply }).await?
+    }
+
+    pub async fn snapshots(&self, depth: usize) -> Result<Vec<(InstrumentId, u64, OrderBookSnapshot)>, String> {
+        self.request(|reply| EngineRequest::BookSnapshots { depth, reply }).await
+    }
+
+    pub async fn take_snapshot(&self) -> Result<EngineSnapshot, String> {
+        self.request(|reply| EngineRequest::EngineSnapshot { reply }).await
+    }
+}
+
+// A caller that gave up waiting has dropped its receiver, so failed replies are ignored
+fn handle_request(engine: &mut MatchingEngine, request: EngineRequest) {
+    match request {
+        EngineRequest::OpenMarket { instrument, lot_size, reply } => {
+            if !engine.has_market(&instrument) {
+                engine.add_new_market_with_lot_size(instrument, lot_size);
+            }
+            let _ = reply.send(());
+        }
+        EngineRequest::PlaceMarket { instrument, mut order, reply } => {
+            let _ = reply.send(engine.place_market_order(instrument, &mut order));
+        }
+        EngineRequest::PlaceLimit { instrument, price, order, reply } => {
+            let _ = reply.send(engine.place_limit_order(instrument, price, order));
+        }
+        EngineRequest::PlaceStop { instrument, stop_price, limit_price, order, reply } => {
+            let _ = reply.send(engine.place_stop_order(instrument, stop_price, limit_price, order));
+        }
+        EngineRequest::Cancel { instrument, order_id, reply } => {
+            let _ = reply.send(engine.cancel_order(instrument, order_id));
+        }
+        EngineRequest::ExpireDayOrders { reply } => {
+            let _ = reply.send(engine.expire_day_orders());
+        }
+        EngineRequest::CostToFill { instrument, side, size, reply } => {
+            let _ = reply.send(engine.cost_to_fill(&instrument, side, size));
+        }
+        EngineRequest::BookSnapshot { instrument, depth, reply } => {
+            let _ = reply.send(engine.snapshot(&instrument, depth));
+        }
+        EngineRequest::BookSnapshots { depth, reply } => {
+            let snapshots = engine
+                .markets()
+                .filter_map(|instrument| {
+                    let (sequence, snapshot) = engine.snapshot(instrument, depth).ok()?;
+                    Some((instrument.clone(), sequence, snapshot))
+                })
+                .collect();
+            let _ = reply.send(snapshots);
+        }
+        EngineRequest::EngineSnapshot { reply } => {
+            let _ = reply.send(engine.take_snapshot());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn requests_from_many_tasks_are_matched_one_at_a_time() {
+        let engine = EngineHandle::spawn(MatchingEngine::new()).unwrap();
+        let instrument = InstrumentId::spot("BTC", "USD");
+        engine.open_market(instrument.clone(), dec!(0.01)).await.unwrap();
+
+        let asks: Vec<_> = (1..=50)
+            .map(|id| {
+                let engine = engine.clone();
+                let instrument = instrument.clone();
+                tokio::spawn(async move {
+                    engine.place_limit_order(instrument, dec!(100), Order::with_id(id, id, BidOrAsk::Ask, dec!(1))).await
+                })
+            })
+            .collect();
+        for ask in asks {
+            assert!(ask.await.unwrap().unwrap().fills.is_empty());
+        }
+
+        let execution = engine
+            .place_market_order(instrument.clone(), Order::with_id(51, 51, BidOrAsk::Bid, dec!(50)))
+            .await
+            .unwrap();
+        assert_eq!(execution.fills.iter().map(|fill| fill.size).sum::<Decimal>(), dec!(50));
+
+        let (_, book) = engine.snapshot(instrument.clone(), 10).await.unwrap();
+        assert!(book.asks.is_empty());
+        assert!(engine.cancel_order(instrument, 1).await.is_err());
+    }
+}
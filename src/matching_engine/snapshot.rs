@@ -0,0 +1,180 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use super::engine::MatchingEngine;
+use crate::models::InstrumentId;
+use super::journal::{read_journal, replay, ReplayReport};
+use super::orderbook::{Limit, Order};
+use super::trigger_book::StopOrder;
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXTENSION: &str = "json";
+
+// Point-in-time state of the whole engine. Recovery loads it and replays the records of
+// `journal_file` after `journal_sequence`, instead of the journal from its start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub taken_at: DateTime<Utc>,
+    // None when the engine was not journaling, in which case there is no tail to replay
+    pub journal_file: Option<String>,
+    pub journal_sequence: u64,
+    pub markets: Vec<MarketSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketSnapshot {
+    pub instrument: InstrumentId,
+    pub lot_size: Decimal,
+    // Market data sequence, so subscribers see it carry on across a recovery
+    pub sequence: u64,
+    pub last_trade_price: Option<Decimal>,
+    // Best price first, each level's orders in queue priority
+    pub bids: Vec<RestingLevel>,
+    pub asks: Vec<RestingLevel>,
+    // In trigger priority
+    pub stops: Vec<StopOrder>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestingLevel {
+    pub price: Decimal,
+    pub orders: Vec<Order>,
+}
+
+impl RestingLevel {
+    pub fn from_limit(limit: &Limit) -> Self {
+        RestingLevel { price: limit.price(), orders: limit.orders().cloned().collect() }
+    }
+}
+
+impl EngineSnapshot {
+    // Named after when it was taken, to the nanosecond, so names sort oldest first. Written to
+    // a temporary file first so a crash never leaves a partial snapshot behind, then linked
+    // into place, which fails rather than replace a snapshot of the same name.
+    pub fn write(&self, dir: &Path) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let name = format!("{}{}", SNAPSHOT_PREFIX, self.taken_at.format("%Y%m%dT%H%M%S%.9fZ"));
+        // Not `with_extension`, which would take the fraction of a second for an extension
+        let path = dir.join(format!("{}.{}", name, SNAPSHOT_EXTENSION));
+        let temporary = dir.join(format!("{}.tmp", name));
+
+        let mut file = File::create_new(&temporary)?;
+        let written = file
+            .write_all(&serde_json::to_vec(self)?)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::hard_link(&temporary, &path));
+        fs::remove_file(&temporary)?;
+        written.map(|_| path)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let contents = fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        serde_json::from_slice(&contents).map_err(|e| format!("Cannot parse {}: {}", path.display(), e))
+    }
+}
+
+// Snapshot files in `dir`, oldest first
+fn snapshot_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|extension| extension == SNAPSHOT_EXTENSION)
+                && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(SNAPSHOT_PREFIX))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// The newest snapshot that can be read; unreadable ones are reported and passed over
+pub fn latest_snapshot(dir: &Path) -> Option<(PathBuf, EngineSnapshot)> {
+    for path in snapshot_files(dir).into_iter().rev() {
+        match EngineSnapshot::read(&path) {
+            Ok(snapshot) => return Some((path, snapshot)),
+            Err(e) => eprintln!("Skipping snapshot: {}", e),
+        }
+    }
+    None
+}
+
+// Removes all but the newest `keep` snapshots
+pub fn prune_snapshots(dir: &Path, keep: usize) -> io::Result<usize> {
+    let files = snapshot_files(dir);
+    let stale = files.len().saturating_sub(keep);
+    for path in &files[..stale] {
+        fs::remove_file(path)?;
+    }
+    Ok(stale)
+}
+
+// The engine state at the end of the snapshot's journal: the snapshot is loaded into a
+// scratch engine and the journal tail after it replayed on top
+pub fn recover_state(dir: &Path, snapshot: &EngineSnapshot) -> Result<(EngineSnapshot, ReplayReport), String> {
+    let mut engine = MatchingEngine::new();
+    engine.restore_snapshot(snapshot)?;
+
+    let report = match &snapshot.journal_file {
+        Some(file) => {
+            let tail = read_journal(&dir.join(file))?
+                .into_iter()
+                .filter(|(sequence, _)| *sequence > snapshot.journal_sequence)
+                .collect();
+            replay(&mut engine, tail)
+        }
+        None => ReplayReport::default(),
+    };
+    Ok((engine.take_snapshot(), report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use super::super::journal::Journal;
+    use super::super::orderbook::BidOrAsk;
+
+    #[test]
+    fn recovery_replays_only_the_journal_after_the_snapshot() {
+        let dir = std::env::temp_dir().join(format!("snapshot-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut engine = MatchingEngine::with_journal(Journal::create(&dir).unwrap());
+        let instrument = InstrumentId::spot("BTC", "USD");
+        engine.add_new_market(instrument.clone());
+        engine.place_limit_order(instrument.clone(), dec!(100), Order::with_id(1, 11, BidOrAsk::Ask, dec!(2))).unwrap();
+        engine.place_limit_order(instrument.clone(), dec!(100), Order::with_id(2, 12, BidOrAsk::Ask, dec!(1))).unwrap();
+        engine.place_stop_order(instrument.clone(), dec!(100), Some(dec!(99)), Order::with_id(3, 13, BidOrAsk::Ask, dec!(1))).unwrap();
+        let snapshot = engine.take_snapshot();
+        snapshot.write(&dir).unwrap();
+        assert_eq!(snapshot.write(&dir).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+
+        let mut market_order = Order::with_id(4, 14, BidOrAsk::Bid, dec!(1));
+        engine.place_market_order(instrument.clone(), &mut market_order).unwrap();
+        engine.place_limit_order(instrument.clone(), dec!(98), Order::with_id(5, 15, BidOrAsk::Bid, dec!(1))).unwrap();
+
+        let (_, snapshot) = latest_snapshot(&dir).unwrap();
+        assert_eq!(snapshot.journal_sequence, 8);
+        let (state, report) = recover_state(&dir, &snapshot).unwrap();
+        assert_eq!(report.commands, 2);
+        assert!(report.is_identical());
+
+        // The recovered engine carries on exactly where the original left off
+        let mut recovered = MatchingEngine::new();
+        recovered.restore_snapshot(&state).unwrap();
+        let expected = serde_json::to_string(&engine.take_snapshot().markets).unwrap();
+        assert_eq!(serde_json::to_string(&recovered.take_snapshot().markets).unwrap(), expected);
+
+        let mut next = Order::with_id(6, 16, BidOrAsk::Bid, dec!(3));
+        let fills = |engine: &mut MatchingEngine, order: &mut Order| -> Vec<(i32, Decimal)> {
+            let execution = engine.place_market_order(instrument.clone(), order).unwrap();
+            execution.fills.iter().map(|fill| (fill.maker_order_id, fill.size)).collect()
+        };
+        assert_eq!(fills(&mut recovered, &mut next.clone()), fills(&mut engine, &mut next));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::models::TimeInForce;
+use super::orderbook::{BidOrAsk, Order};
+
+// A stop waiting for the market to trade through its stop price. Once triggered it
+// enters the book as a market order, or as a limit order when it has a limit price.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StopOrder {
+    pub order: Order,
+    pub stop_price: Decimal,
+    pub limit_price: Option<Decimal>,
+}
+
+// Pending stops of one market. Buy stops fire when the last trade is at or above their
+// stop price, sell stops when it is at or below. Stops fired by the same trade come out
+// in stop price priority (lowest buy / highest sell first), then in arrival order.
+#[derive(Debug, Clone, Default)]
+pub struct TriggerBook {
+    buy_stops: BTreeMap<Decimal, VecDeque<StopOrder>>,
+    sell_stops: BTreeMap<Decimal, VecDeque<StopOrder>>,
+    index: HashMap<i32, (BidOrAsk, Decimal)>,
+    last_trade_price: Option<Decimal>,
+}
+
+impl TriggerBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn last_trade_price(&self) -> Option<Decimal> {
+        self.last_trade_price
+    }
+
+    // Restores the reference price after a restart without firing anything
+    pub fn set_last_trade_price(&mut self, price: Decimal) {
+        self.last_trade_price = Some(price);
+    }
+
+    pub fn is_triggered(&self, side: BidOrAsk, stop_price: Decimal) -> bool {
+        match (side, self.last_trade_price) {
+            (_, None) => false,
+            (BidOrAsk::Bid, Some(last)) => last >= stop_price,
+            (BidOrAsk::Ask, Some(last)) => last <= stop_price,
+        }
+    }
+
+    pub fn contains_order(&self, order_id: i32) -> bool {
+        self.index.contains_key(&order_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    // Buy stops then sell stops, each by stop price and then arrival, so adding them back
+    // in this order rebuilds the same book
+    pub fn stops(&self) -> impl Iterator<Item = &StopOrder> {
+        self.buy_stops.values().chain(self.sell_stops.values()).flatten()
+    }
+
+    pub fn add_stop_order(&mut self, stop: StopOrder) {
+        let side = stop.order.bid_or_ask();
+        let stops = match side {
+            BidOrAsk::Bid => &mut self.buy_stops,
+            BidOrAsk::Ask => &mut self.sell_stops,
+        };
+        self.index.insert(stop.order.id(), (side, stop.stop_price));
+        stops.entry(stop.stop_price).or_default().push_back(stop);
+    }
+
+    pub fn cancel_order(&mut self, order_id: i32) -> Option<StopOrder> {
+        let (side, stop_price) = self.index.remove(&order_id)?;
+        let stops = match side {
+            BidOrAsk::Bid => &mut self.buy_stops,
+            BidOrAsk::Ask => &mut self.sell_stops,
+        };
+        let level = stops.get_mut(&stop_price)?;
+        let position = level.iter().position(|stop| stop.order.id() == order_id)?;
+        let stop = level.remove(position);
+        if level.is_empty() {
+            stops.remove(&stop_price);
+        }
+        stop
+    }
+
+    // Records a trade and hands back every stop it fires
+    pub fn take_triggered(&mut self, trade_price: Decimal) -> Vec<StopOrder> {
+        self.last_trade_price = Some(trade_price);
+        let mut triggered = Vec::new();
+
+        while let Some(entry) = self.buy_stops.first_entry() {
+            if *entry.key() > trade_price {
+                break;
+            }
+            triggered.extend(entry.remove());
+        }
+        while let Some(entry) = self.sell_stops.last_entry() {
+            if *entry.key() < trade_price {
+                break;
+            }
+            triggered.extend(entry.remove());
+        }
+
+        for stop in &triggered {
+            self.index.remove(&stop.order.id());
+        }
+        triggered
+    }
+
+    pub fn expire_day_orders(&mut self) -> Vec<StopOrder> {
+        let day_orders: Vec<i32> = self
+            .buy_stops
+            .values()
+            .chain(self.sell_stops.values())
+            .flatten()
+            .filter(|stop| *stop.order.time_in_force() == TimeInForce::DAY)
+            .map(|stop| stop.order.id())
+            .collect();
+        day_orders
+            .into_iter()
+            .filter_map(|order_id| self.cancel_order(order_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn stop(id: i32, side: BidOrAsk, stop_price: Decimal) -> StopOrder {
+        StopOrder {
+            order: Order::with_id(id, 100 + id, side, dec!(1)),
+            stop_price,
+            limit_price: None,
+        }
+    }
+
+    #[test]
+    fn stops_fire_when_the_market_trades_through_them() {
+        let mut triggers = TriggerBook::new();
+        triggers.add_stop_order(stop(1, BidOrAsk::Bid, dec!(105)));
+        triggers.add_stop_order(stop(2, BidOrAsk::Bid, dec!(103)));
+        triggers.add_stop_order(stop(3, BidOrAsk::Ask, dec!(95)));
+        triggers.add_stop_order(stop(4, BidOrAsk::Ask, dec!(97)));
+
+        assert!(triggers.take_triggered(dec!(100)).is_empty());
+
+        let fired: Vec<i32> = triggers.take_triggered(dec!(105)).iter().map(|stop| stop.order.id()).collect();
+        assert_eq!(fired, vec![2, 1]);
+
+        let fired: Vec<i32> = triggers.take_triggered(dec!(90)).iter().map(|stop| stop.order.id()).collect();
+        assert_eq!(fired, vec![4, 3]);
+        assert!(triggers.is_empty());
+    }
+
+    #[test]
+    fn cancelled_stops_never_fire() {
+        let mut triggers = TriggerBook::new();
+        triggers.add_stop_order(stop(1, BidOrAsk::Bid, dec!(105)));
+        triggers.add_stop_order(stop(2, BidOrAsk::Bid, dec!(105)));
+
+        assert_eq!(triggers.cancel_order(1).unwrap().order.id(), 1);
+        assert!(triggers.cancel_order(1).is_none());
+
+        let fired: Vec<i32> = triggers.take_triggered(dec!(110)).iter().map(|stop| stop.order.id()).collect();
+        assert_eq!(fired, vec![2]);
+        assert!(triggers.is_triggered(BidOrAsk::Bid, dec!(110)));
+        assert!(!triggers.is_triggered(BidOrAsk::Ask, dec!(105)));
+    }
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+use chrono::{DateTime, Utc};
+
+// How much of one asset a user holds: `available` can be spent or withdrawn, `locked` is
+// held by their open orders
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Balance {
+    pub user_id: i32,
+    pub asset: String,
+    pub available: Decimal,
+    pub locked: Decimal,
+    pub updated_at: DateTime<Utc>,
+}
+
+// A deposit or withdrawal of `amount` of `asset`
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+    pub asset: String,
+    pub amount: Decimal,
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+
+// Which side of a trade an order was on: the maker rested on the book, the taker crossed it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl std::fmt::Display for Liquidity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Liquidity::Maker => write!(f, "maker"),
+            Liquidity::Taker => write!(f, "taker"),
+        }
+    }
+}
+
+impl std::str::FromStr for Liquidity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "maker" => Ok(Liquidity::Maker),
+            "taker" => Ok(Liquidity::Taker),
+            _ => Err(format!("unknown liquidity: {}", s)),
+        }
+    }
+}
+
+// Rates apply from `min_volume` of trailing notional upwards
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeTier {
+    pub min_volume: Decimal,
+    pub maker_rate: Decimal,
+    pub taker_rate: Decimal,
+}
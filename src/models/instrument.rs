@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+use chrono::{DateTime, Utc};
+use super::InstrumentId;
+
+// A tradable symbol and the constraints every order for it has to meet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instrument {
+    pub symbol: InstrumentId,
+    pub tick_size: Decimal,       // Prices must be a multiple of this
+    pub lot_size: Decimal,        // Quantities must be a multiple of this
+    pub min_quantity: Decimal,
+    pub max_quantity: Option<Decimal>,
+    pub min_notional: Decimal,    // Smallest quantity * price accepted
+    pub price_precision: u32,     // Decimal places a price may have
+    pub status: MarketStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MarketStatus {
+    Active,
+    Halted, // No new orders are accepted; resting orders can still be cancelled
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInstrumentRequest {
+    pub symbol: InstrumentId,
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+    pub min_quantity: Option<Decimal>,
+    pub max_quantity: Option<Decimal>,
+    pub min_notional: Option<Decimal>,
+    pub price_precision: Option<u32>,
+}
+
+impl std::fmt::Display for MarketStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarketStatus::Active => write!(f, "active"),
+            MarketStatus::Halted => write!(f, "halted"),
+        }
+    }
+}
+
+impl std::str::FromStr for MarketStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(MarketStatus::Active),
+            "halted" => Ok(MarketStatus::Halted),
+            _ => Err(format!("unknown market status: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Equities trade against, and settle in, this currency
+pub const EQUITY_CURRENCY: &str = "USD";
+
+// Identifies what an order, trade, position or market is for. Spot pairs are written
+// `BASE/QUOTE` (`BTC/USD`) and trade one asset against another; equities are a bare
+// ticker (`AAPL`) priced in `EQUITY_CURRENCY`. `BASE-QUOTE` is read as a spot pair too,
+// which keeps pairs usable in URL paths.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InstrumentId {
+    Spot { base: String, quote: String },
+    Equity { ticker: String },
+}
+
+impl InstrumentId {
+    pub fn spot(base: &str, quote: &str) -> Self {
+        InstrumentId::Spot { base: base.to_uppercase(), quote: quote.to_uppercase() }
+    }
+
+    pub fn equity(ticker: &str) -> Self {
+        InstrumentId::Equity { ticker: ticker.to_uppercase() }
+    }
+
+    // The asset bought and sold: the base of a pair, or the equity itself
+    pub fn base(&self) -> &str {
+        match self {
+            InstrumentId::Spot { base, .. } => base,
+            InstrumentId::Equity { ticker } => ticker,
+        }
+    }
+
+    // The asset prices are in and paid with
+    pub fn quote(&self) -> &str {
+        match self {
+            InstrumentId::Spot { quote, .. } => quote,
+            InstrumentId::Equity { .. } => EQUITY_CURRENCY,
+        }
+    }
+}
+
+impl std::fmt::Display for InstrumentId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstrumentId::Spot { base, quote } => write!(f, "{}/{}", base, quote),
+            InstrumentId::Equity { ticker } => write!(f, "{}", ticker),
+        }
+    }
+}
+
+impl std::str::FromStr for InstrumentId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let is_code = |code: &str| !code.is_empty() && code.chars().all(|c| c.is_ascii_alphanumeric());
+        match s.split_once(['/', '-']) {
+            Some((base, quote)) if is_code(base) && is_code(quote) => Ok(InstrumentId::spot(base, quote)),
+            None if is_code(s) => Ok(InstrumentId::equity(s)),
+            _ => Err(format!("invalid instrument: {:?}", s)),
+        }
+    }
+}
+
+impl Serialize for InstrumentId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for InstrumentId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instruments_round_trip_through_their_written_form() {
+        let pair: InstrumentId = "btc-usd".parse().unwrap();
+        assert_eq!(pair, InstrumentId::spot("BTC", "USD"));
+        assert_eq!(pair.to_string(), "BTC/USD");
+        assert_eq!((pair.base(), pair.quote()), ("BTC", "USD"));
+
+        let equity: InstrumentId = "AAPL".parse().unwrap();
+        assert_eq!((equity.base(), equity.quote()), ("AAPL", EQUITY_CURRENCY));
+        assert_eq!(serde_json::to_string(&equity).unwrap(), "\"AAPL\"");
+        assert_eq!(serde_json::from_str::<InstrumentId>("\"ETH/BTC\"").unwrap(), InstrumentId::spot("ETH", "BTC"));
+
+        for invalid in ["", "BTC/", "/USD", "BTC/USD/EUR", "BT C"] {
+            assert!(invalid.parse::<InstrumentId>().is_err(), "{:?} parsed", invalid);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+use chrono::{DateTime, Utc};
+
+// What caused a ledger entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerEntryKind {
+    Deposit,
+    Withdrawal,
+    Trade,      // Both legs of a trade; references the trade
+    Fee,        // References the trade the fee was charged on
+    Adjustment, // Manual correction, with the reason as its description
+}
+
+impl std::fmt::Display for LedgerEntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LedgerEntryKind::Deposit => write!(f, "deposit"),
+            LedgerEntryKind::Withdrawal => write!(f, "withdrawal"),
+            LedgerEntryKind::Trade => write!(f, "trade"),
+            LedgerEntryKind::Fee => write!(f, "fee"),
+            LedgerEntryKind::Adjustment => write!(f, "adjustment"),
+        }
+    }
+}
+
+impl std::str::FromStr for LedgerEntryKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(LedgerEntryKind::Deposit),
+            "withdrawal" => Ok(LedgerEntryKind::Withdrawal),
+            "trade" => Ok(LedgerEntryKind::Trade),
+            "fee" => Ok(LedgerEntryKind::Fee),
+            "adjustment" => Ok(LedgerEntryKind::Adjustment),
+            _ => Err(format!("unknown ledger entry kind: {}", s)),
+        }
+    }
+}
+
+// One posting to a user's account, with the account's balance in the asset after it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatementLine {
+    pub entry_id: i32,
+    pub kind: LedgerEntryKind,
+    pub reference_id: Option<i32>,
+    pub description: Option<String>,
+    pub asset: String,
+    pub amount: Decimal,
+    pub balance: Decimal,
+    pub created_at: DateTime<Utc>,
+}
+
+// A user's balance of an asset (available plus locked) that does not match their ledger account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceDrift {
+    pub user_id: i32,
+    pub asset: String,
+    pub balance: Decimal,
+    pub ledger: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    pub checked_at: DateTime<Utc>,
+    pub drifts: Vec<BalanceDrift>,
+    // Entries whose postings do not sum to zero in every asset
+    pub unbalanced_entries: Vec<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdjustmentRequest {
+    pub user_id: i32,
+    pub asset: String,
+    pub amount: Decimal, // Negative to take from the user
+    pub reason: String,
+}
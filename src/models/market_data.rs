@@ -1,10 +1,11 @@
 use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use chrono::{DateTime, Utc};
+use super::{InstrumentId, OrderBookSnapshot, OrderSide, QuoteLevel};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MarketData {
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub best_bid: Option<Decimal>,
     pub best_ask: Option<Decimal>,
     pub mid_price: Option<Decimal>,
@@ -15,7 +16,7 @@ pub struct MarketData {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MarketSummary {
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub last_price: Option<Decimal>,
     pub price_change_24h: Option<Decimal>,
     pub price_change_percent_24h: Option<Decimal>,
@@ -30,11 +31,55 @@ pub struct MarketSummary {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ticker {
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub price: Decimal,
     pub timestamp: DateTime<Utc>,
 }
 
+// Sent by WebSocket clients to choose the symbols they receive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MarketDataRequest {
+    Subscribe { symbols: Vec<InstrumentId> },
+    Unsubscribe { symbols: Vec<InstrumentId> },
+}
+
+// Pushed to WebSocket clients. Sequence numbers are per symbol and shared by all message
+// types: a subscription starts with a snapshot, and every later message of that symbol
+// carries the next number. A gap means messages were lost and a new snapshot follows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MarketDataMessage {
+    Snapshot {
+        sequence: u64,
+        #[serde(flatten)]
+        book: OrderBookSnapshot,
+    },
+    // Changed levels only; a quantity of zero removes the level
+    Update {
+        symbol: InstrumentId,
+        sequence: u64,
+        bids: Vec<QuoteLevel>,
+        asks: Vec<QuoteLevel>,
+    },
+    Trade {
+        symbol: InstrumentId,
+        sequence: u64,
+        price: Decimal,
+        quantity: Decimal,
+        aggressor_side: OrderSide,
+        timestamp: DateTime<Utc>,
+    },
+    Ticker {
+        sequence: u64,
+        #[serde(flatten)]
+        ticker: Ticker,
+    },
+    Error {
+        message: String,
+    },
+}
+
 impl MarketData {
     pub fn get_spread(&self) -> Option<Decimal> {
         match (self.best_bid, self.best_ask) {
@@ -1,13 +1,59 @@
+// Stores types that have a written form (`Display` and `FromStr`) in text columns. A value
+// that does not parse is an error, never a default.
+macro_rules! text_column {
+    ($($ty:ty),+ $(,)?) => {$(
+        impl<'a> postgres_types::FromSql<'a> for $ty {
+            fn from_sql(ty: &postgres_types::Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+                Ok(<&str as postgres_types::FromSql>::from_sql(ty, raw)?.parse::<$ty>()?)
+            }
+
+            fn accepts(ty: &postgres_types::Type) -> bool {
+                <&str as postgres_types::FromSql>::accepts(ty)
+            }
+        }
+
+        impl postgres_types::ToSql for $ty {
+            fn to_sql(
+                &self,
+                ty: &postgres_types::Type,
+                out: &mut bytes::BytesMut,
+            ) -> Result<postgres_types::IsNull, Box<dyn std::error::Error + Sync + Send>> {
+                postgres_types::ToSql::to_sql(&self.to_string(), ty, out)
+            }
+
+            fn accepts(ty: &postgres_types::Type) -> bool {
+                <String as postgres_types::ToSql>::accepts(ty)
+            }
+
+            postgres_types::to_sql_checked!();
+        }
+    )+};
+}
+
 pub mod user;
 pub mod order;
 pub mod trade;
 pub mod position;
 pub mod order_book;
 pub mod market_data;
+pub mod instrument;
+pub mod instrument_id;
+pub mod balance;
+pub mod ledger;
+pub mod fee;
+pub mod validation;
 
 pub use user::*;
 pub use order::*;
 pub use trade::*;
 pub use position::*;
 pub use order_book::*;
-pub use market_data::*;
\ No newline at end of file
+pub use market_data::*;
+pub use instrument::*;
+pub use instrument_id::*;
+pub use balance::*;
+pub use ledger::*;
+pub use fee::*;
+pub use validation::*;
+
+text_column!(InstrumentId, OrderSide, OrderType, OrderStatus, TimeInForce, RejectReason, MarketStatus, LedgerEntryKind, Liquidity);
@@ -1,40 +1,44 @@
 use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use chrono::{DateTime, Utc};
+use super::{FieldError, FieldErrors, InstrumentId, Trade};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     pub order_id: i32,
     pub user_id: i32,
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub side: OrderSide,
     pub order_type: OrderType,
     pub quantity: Decimal,
     pub limit_price: Option<Decimal>,
+    pub stop_price: Option<Decimal>,
     pub filled_quantity: Decimal,
     pub remaining_quantity: Decimal,
     pub status: OrderStatus,
+    pub reject_reason: Option<RejectReason>,
     pub time_in_force: TimeInForce,
     pub submission_time: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum OrderSide {
     Buy,
     Sell,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum OrderType {
     Limit,
     Market,
-    Stop,
+    Stop,      // Market order once the stop price trades
+    StopLimit, // Limit order once the stop price trades
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum OrderStatus {
     Pending,
@@ -44,7 +48,18 @@ pub enum OrderStatus {
     Rejected,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Why an order ended up rejected; stored with the order and returned to the caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    InsufficientFunds,   // Buy costs more than the cash not already reserved by open buys
+    InsufficientPosition, // Sell is larger than the position not already offered by open sells
+    FillOrKillUnfilled,  // FOK order could not be filled completely on entry
+    EngineRejected,      // The matching engine refused the order
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TimeInForce {
     GTC, // Good Till Cancelled
     IOC, // Immediate or Cancel
@@ -55,20 +70,68 @@ pub enum TimeInForce {
 #[derive(Debug, Deserialize)]
 pub struct CreateOrderRequest {
     pub user_id: i32,
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub side: OrderSide,
     pub order_type: OrderType,
     pub quantity: Decimal,
     pub limit_price: Option<Decimal>,
+    pub stop_price: Option<Decimal>,
     pub time_in_force: Option<TimeInForce>,
 }
 
+impl CreateOrderRequest {
+    // The prices an order carries depend on its type: limit and stop-limit orders need a
+    // limit price, stop and stop-limit orders a stop price, and no order may carry the other.
+    // Whether the order suits its instrument is checked against the registry later.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = FieldErrors::default();
+        errors.positive("quantity", self.quantity);
+
+        let prices = [
+            ("limit_price", self.limit_price, self.order_type.has_limit_price()),
+            ("stop_price", self.stop_price, self.order_type.is_stop()),
+        ];
+        for (field, price, needed) in prices {
+            match (price, needed) {
+                (Some(price), true) => errors.positive(field, price),
+                (None, true) => errors.add(field, format!("is required for {} orders", self.order_type)),
+                (Some(_), false) => errors.add(field, format!("is not allowed for {} orders", self.order_type)),
+                (None, false) => {}
+            }
+        }
+        errors.into_result()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderExecution {
+    pub order: Order,
+    pub fills: Vec<Trade>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CancelOrderRequest {
     pub order_id: i32,
     pub user_id: i32,
 }
 
+impl OrderType {
+    pub fn is_stop(&self) -> bool {
+        matches!(self, OrderType::Stop | OrderType::StopLimit)
+    }
+
+    pub fn has_limit_price(&self) -> bool {
+        matches!(self, OrderType::Limit | OrderType::StopLimit)
+    }
+}
+
+impl TimeInForce {
+    // Whether an unfilled remainder may stay on the book
+    pub fn can_rest(&self) -> bool {
+        matches!(self, TimeInForce::GTC | TimeInForce::DAY)
+    }
+}
+
 impl std::fmt::Display for OrderSide {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -78,12 +141,39 @@ impl std::fmt::Display for OrderSide {
     }
 }
 
+impl std::str::FromStr for OrderSide {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buy" => Ok(OrderSide::Buy),
+            "sell" => Ok(OrderSide::Sell),
+            _ => Err(format!("unknown order side: {}", s)),
+        }
+    }
+}
+
 impl std::fmt::Display for OrderType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             OrderType::Limit => write!(f, "limit"),
             OrderType::Market => write!(f, "market"),
             OrderType::Stop => write!(f, "stop"),
+            OrderType::StopLimit => write!(f, "stop_limit"),
+        }
+    }
+}
+
+impl std::str::FromStr for OrderType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "limit" => Ok(OrderType::Limit),
+            "market" => Ok(OrderType::Market),
+            "stop" => Ok(OrderType::Stop),
+            "stop_limit" => Ok(OrderType::StopLimit),
+            _ => Err(format!("unknown order type: {}", s)),
         }
     }
 }
@@ -100,6 +190,46 @@ impl std::fmt::Display for OrderStatus {
     }
 }
 
+impl std::str::FromStr for OrderStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(OrderStatus::Pending),
+            "active" => Ok(OrderStatus::Active),
+            "filled" => Ok(OrderStatus::Filled),
+            "cancelled" => Ok(OrderStatus::Cancelled),
+            "rejected" => Ok(OrderStatus::Rejected),
+            _ => Err(format!("unknown order status: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::InsufficientFunds => write!(f, "insufficient_funds"),
+            RejectReason::InsufficientPosition => write!(f, "insufficient_position"),
+            RejectReason::FillOrKillUnfilled => write!(f, "fill_or_kill_unfilled"),
+            RejectReason::EngineRejected => write!(f, "engine_rejected"),
+        }
+    }
+}
+
+impl std::str::FromStr for RejectReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "insufficient_funds" => Ok(RejectReason::InsufficientFunds),
+            "insufficient_position" => Ok(RejectReason::InsufficientPosition),
+            "fill_or_kill_unfilled" => Ok(RejectReason::FillOrKillUnfilled),
+            "engine_rejected" => Ok(RejectReason::EngineRejected),
+            _ => Err(format!("unknown reject reason: {}", s)),
+        }
+    }
+}
+
 impl std::fmt::Display for TimeInForce {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -109,4 +239,81 @@ impl std::fmt::Display for TimeInForce {
             TimeInForce::DAY => write!(f, "DAY"),
         }
     }
-}
\ No newline at end of file
+}
+
+impl std::str::FromStr for TimeInForce {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "GTC" => Ok(TimeInForce::GTC),
+            "IOC" => Ok(TimeInForce::IOC),
+            "FOK" => Ok(TimeInForce::FOK),
+            "DAY" => Ok(TimeInForce::DAY),
+            _ => Err(format!("unknown time in force: {}", s)),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use postgres_types::{FromSql, ToSql, Type};
+
+    fn round_trip<T: ToSql + for<'a> FromSql<'a>>(value: &T) -> T {
+        let mut buf = BytesMut::new();
+        value.to_sql(&Type::VARCHAR, &mut buf).unwrap();
+        T::from_sql(&Type::VARCHAR, &buf).unwrap()
+    }
+
+    #[test]
+    fn enums_are_stored_in_their_written_form_and_unknown_values_are_errors() {
+        for side in [OrderSide::Buy, OrderSide::Sell] {
+            assert_eq!(round_trip(&side), side);
+        }
+        for order_type in [OrderType::Limit, OrderType::Market, OrderType::Stop, OrderType::StopLimit] {
+            assert_eq!(round_trip(&order_type), order_type);
+        }
+        for status in [OrderStatus::Pending, OrderStatus::Active, OrderStatus::Filled, OrderStatus::Cancelled, OrderStatus::Rejected] {
+            assert_eq!(round_trip(&status), status);
+        }
+        for time_in_force in [TimeInForce::GTC, TimeInForce::IOC, TimeInForce::FOK, TimeInForce::DAY] {
+            assert_eq!(round_trip(&time_in_force), time_in_force);
+        }
+
+        assert!(OrderSide::from_sql(&Type::VARCHAR, b"sideways").is_err());
+        assert!(OrderStatus::from_sql(&Type::VARCHAR, b"").is_err());
+        assert!(!<OrderType as FromSql>::accepts(&Type::INT4));
+    }
+
+    fn order_request(order_type: OrderType, limit_price: Option<Decimal>, stop_price: Option<Decimal>) -> CreateOrderRequest {
+        CreateOrderRequest {
+            user_id: 1,
+            symbol: InstrumentId::spot("BTC", "USD"),
+            side: OrderSide::Buy,
+            order_type,
+            quantity: Decimal::ONE,
+            limit_price,
+            stop_price,
+            time_in_force: None,
+        }
+    }
+
+    #[test]
+    fn orders_carry_exactly_the_prices_their_type_needs() {
+        let price = Some(Decimal::from(100));
+        assert!(order_request(OrderType::Market, None, None).validate().is_ok());
+        assert!(order_request(OrderType::StopLimit, price, price).validate().is_ok());
+
+        let fields = |request: CreateOrderRequest| -> Vec<&'static str> {
+            request.validate().unwrap_err().into_iter().map(|error| error.field).collect()
+        };
+        assert_eq!(fields(order_request(OrderType::Limit, None, None)), ["limit_price"]);
+        assert_eq!(fields(order_request(OrderType::Market, price, None)), ["limit_price"]);
+        assert_eq!(fields(order_request(OrderType::Stop, price, None)), ["limit_price", "stop_price"]);
+
+        let mut request = order_request(OrderType::Limit, Some(Decimal::new(1, 9)), None);
+        request.quantity = -Decimal::ONE;
+        assert_eq!(fields(request), ["quantity", "limit_price"]);
+    }
+}
@@ -1,11 +1,12 @@
 use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use chrono::{DateTime, Utc};
+use super::InstrumentId;
 use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBook {
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub bids: BTreeMap<Decimal, PriceLevel>,
     pub asks: BTreeMap<Decimal, PriceLevel>,
     pub best_bid: Option<Decimal>,
@@ -32,7 +33,7 @@ pub struct OrderBookEntry {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBookSnapshot {
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub bids: Vec<QuoteLevel>,
     pub asks: Vec<QuoteLevel>,
     pub best_bid: Option<Decimal>,
@@ -50,7 +51,7 @@ pub struct QuoteLevel {
 }
 
 impl OrderBook {
-    pub fn new(symbol: String) -> Self {
+    pub fn new(symbol: InstrumentId) -> Self {
         Self {
             symbol,
             bids: BTreeMap::new(),
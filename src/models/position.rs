@@ -1,12 +1,13 @@
 use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use chrono::{DateTime, Utc};
+use super::InstrumentId;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Position {
     pub position_id: i32,
     pub user_id: i32,
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub quantity: Decimal,
     pub avg_cost: Decimal,
     pub updated_at: DateTime<Utc>,
@@ -14,7 +15,7 @@ pub struct Position {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PositionSummary {
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub quantity: Decimal,
     pub avg_cost: Decimal,
     pub market_value: Option<Decimal>,
@@ -1,12 +1,12 @@
 use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use chrono::{DateTime, Utc};
-use super::OrderSide;
+use super::{InstrumentId, OrderSide};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     pub trade_id: i32,
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub price: Decimal,
     pub quantity: Decimal,
     pub buy_order_id: i32,
@@ -36,7 +36,7 @@ pub struct UserTradeHistory {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserTrade {
     pub trade_id: i32,
-    pub symbol: String,
+    pub symbol: InstrumentId,
     pub side: OrderSide,
     pub price: Decimal,
     pub quantity: Decimal,
@@ -1,13 +1,13 @@
 use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use chrono::{DateTime, Utc};
-use super::Position;
+use super::{column_overflow, Balance, FieldError, FieldErrors, Position};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub user_id: i32,
     pub username: String,
-    pub cash_balance: Decimal,
+    pub cash_balance: Decimal, // Available USD; see `balances` for every asset
     pub realized_pnl: Decimal,
     pub unrealized_pnl: Decimal,
     pub created_at: DateTime<Utc>,
@@ -18,20 +18,45 @@ pub struct User {
 pub struct UserProfile {
     pub user_id: i32,
     pub username: String,
-    pub cash_balance: Decimal,
+    pub cash_balance: Decimal, // Available USD
     pub realized_pnl: Decimal,
     pub unrealized_pnl: Decimal,
+    pub balances: Vec<Balance>,
     pub positions: Vec<Position>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateUserRequest {
     pub username: String,
-    pub initial_balance: Option<Decimal>,
+    pub initial_balance: Option<Decimal>, // Deposited in USD
 }
 
-#[derive(Debug, Deserialize)]
-pub struct UpdateBalanceRequest {
-    pub amount: Decimal,
-    pub operation: String, // "add" or "subtract"
-}
\ No newline at end of file
+// The username column is VARCHAR(50)
+const USERNAME_LENGTH: std::ops::RangeInclusive<usize> = 3..=50;
+
+impl CreateUserRequest {
+    // Usernames are ASCII letters, digits, underscores and hyphens and start with a letter
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = FieldErrors::default();
+        let username = &self.username;
+        if !USERNAME_LENGTH.contains(&username.len()) {
+            errors.add(
+                "username",
+                format!("must be {} to {} characters long", USERNAME_LENGTH.start(), USERNAME_LENGTH.end()),
+            );
+        } else if !username.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            errors.add("username", "must start with a letter");
+        } else if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            errors.add("username", "may only contain letters, digits, underscores and hyphens");
+        }
+
+        if let Some(initial_balance) = self.initial_balance {
+            if initial_balance < Decimal::ZERO {
+                errors.add("initial_balance", "must not be negative");
+            } else if let Some(message) = column_overflow(initial_balance) {
+                errors.add("initial_balance", message);
+            }
+        }
+        errors.into_result()
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Serialize;
+use rust_decimal::Decimal;
+
+// Amounts and prices are stored as DECIMAL(18, 8): 8 decimal places and 10 digits before the point
+pub const DECIMAL_SCALE: u32 = 8;
+const DECIMAL_INTEGER_DIGITS: u32 = 10;
+
+// What is wrong with one field of a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+// Every problem with a request, so a client can fix them all at once
+#[derive(Debug, Default)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.0.push(FieldError { field, message: message.into() });
+    }
+
+    // Checks a value that must be positive and fit its column
+    pub fn positive(&mut self, field: &'static str, value: Decimal) {
+        if value <= Decimal::ZERO {
+            self.add(field, "must be positive");
+        } else if let Some(message) = column_overflow(value) {
+            self.add(field, message);
+        }
+    }
+
+    pub fn into_result(self) -> Result<(), Vec<FieldError>> {
+        if self.0.is_empty() { Ok(()) } else { Err(self.0) }
+    }
+}
+
+// Why a value would not fit a DECIMAL(18, 8) column, if it would not. Postgres would round
+// extra decimal places away rather than refuse them.
+pub fn column_overflow(value: Decimal) -> Option<String> {
+    if value.normalize().scale() > DECIMAL_SCALE {
+        Some(format!("must have at most {} decimal places", DECIMAL_SCALE))
+    } else if value.abs() >= Decimal::from(10u64.pow(DECIMAL_INTEGER_DIGITS)) {
+        Some(format!("must have at most {} digits before the decimal point", DECIMAL_INTEGER_DIGITS))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn values_must_fit_a_decimal_18_8_column() {
+        assert_eq!(column_overflow(dec!(9999999999.99999999)), None);
+        // Trailing zeros do not count as precision
+        assert_eq!(column_overflow(dec!(1.5000000000)), None);
+        assert!(column_overflow(dec!(0.000000001)).is_some());
+        assert!(column_overflow(dec!(10000000000)).is_some());
+        assert!(column_overflow(dec!(-10000000000)).is_some());
+
+        let mut errors = FieldErrors::default();
+        errors.positive("quantity", dec!(0));
+        errors.positive("limit_price", dec!(100));
+        assert_eq!(
+            errors.into_result(),
+            Err(vec![FieldError { field: "quantity", message: "must be positive".to_string() }])
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use rust_decimal::Decimal;
+use super::error::AppError;
+use crate::database::{balances, orders, GenericClient, Transaction};
+use crate::models::{Balance, InstrumentId, OrderSide};
+
+// The asset an order locks: what a buy pays with, or what a sell delivers
+pub fn locked_asset<'a>(symbol: &'a InstrumentId, side: &OrderSide) -> &'a str {
+    match side {
+        OrderSide::Buy => symbol.quote(),
+        OrderSide::Sell => symbol.base(),
+    }
+}
+
+// Splits what a fill costs an order into the part its lock still covers and the part that
+// has to come out of the available balance. Orders are matched within what they locked, so
+// the second part is zero unless something upstream went wrong.
+pub fn split_spend(locked: Decimal, amount: Decimal) -> (Decimal, Decimal) {
+    let from_locked = amount.min(locked.max(Decimal::ZERO));
+    (from_locked, amount - from_locked)
+}
+
+pub async fn load_balances(client: &impl GenericClient, user_id: i32) -> Result<Vec<Balance>, tokio_postgres::Error> {
+    balances::for_user(client, user_id).await
+}
+
+// Deposits, proceeds of trades and, with a negative amount, fees
+pub async fn credit(tx: &Transaction<'_>, user_id: i32, asset: &str, amount: Decimal) -> Result<Balance, tokio_postgres::Error> {
+    balances::adjust(tx, user_id, asset, amount, Decimal::ZERO).await
+}
+
+// None when the available balance does not cover the amount
+pub async fn withdraw(
+    tx: &Transaction<'_>,
+    user_id: i32,
+    asset: &str,
+    amount: Decimal,
+) -> Result<Option<Balance>, tokio_postgres::Error> {
+    balances::withdraw(tx, user_id, asset, amount).await
+}
+
+// Moves what an accepted order may spend from available to locked and records it against
+// the order
+pub async fn lock(
+    tx: &Transaction<'_>,
+    order_id: i32,
+    user_id: i32,
+    asset: &str,
+    amount: Decimal,
+) -> Result<(), tokio_postgres::Error> {
+    balances::adjust(tx, user_id, asset, -amount, amount).await?;
+    orders::set_locked_amount(tx, order_id, amount).await
+}
+
+// Pays for a fill out of what the order has locked, and out of available for any excess.
+// An excess the available balance does not cover fails the settlement rather than leave
+// the balance negative.
+pub async fn spend(
+    tx: &Transaction<'_>,
+    order_id: i32,
+    user_id: i32,
+    asset: &str,
+    amount: Decimal,
+) -> Result<(), AppError> {
+    let locked = orders::locked_amount_for_update(tx, order_id).await?;
+    let (from_locked, from_available) = split_spend(locked, amount);
+
+    orders::spend_locked_amount(tx, order_id, from_locked).await?;
+    balances::adjust(tx, user_id, asset, Decimal::ZERO, -from_locked).await?;
+    if from_available > Decimal::ZERO && balances::withdraw(tx, user_id, asset, from_available).await?.is_none() {
+        return Err(AppError::Internal(format!(
+            "order {} spent {} {} more than it locked and its owner has available",
+            order_id, from_available, asset
+        )));
+    }
+    Ok(())
+}
+
+// Returns whatever is still locked by the given orders that have been filled, cancelled or
+// rejected to their owners' available balances
+pub async fn release_finished(tx: &Transaction<'_>, order_ids: &[i32]) -> Result<(), tokio_postgres::Error> {
+    for released in orders::take_finished_locks(tx, order_ids).await? {
+        let asset = locked_asset(&released.symbol, &released.side);
+        balances::adjust(tx, released.user_id, asset, released.amount, -released.amount).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn fills_spend_the_lock_before_the_available_balance() {
+        let btc_usd = InstrumentId::spot("BTC", "USD");
+        assert_eq!(locked_asset(&btc_usd, &OrderSide::Buy), "USD");
+        assert_eq!(locked_asset(&btc_usd, &OrderSide::Sell), "BTC");
+
+        assert_eq!(split_spend(dec!(1000), dec!(400)), (dec!(400), dec!(0)));
+        assert_eq!(split_spend(dec!(300), dec!(400)), (dec!(300), dec!(100)));
+        assert_eq!(split_spend(dec!(0), dec!(400)), (dec!(0), dec!(400)));
+    }
+}
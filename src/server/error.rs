@@ -0,0 +1,196 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::PoisonError;
+use tokio_postgres::error::SqlState;
+use crate::database::PoolError;
+use crate::models::FieldError;
+use crate::matching_engine::sequencer;
+
+// Why a request failed. Every variant is sent as a JSON body of the form
+// `{"code": ..., "message": ..., "details": ...}` with the matching status.
+#[derive(Debug)]
+pub enum AppError {
+    // The request itself is wrong; `details` says what, when there is more than the message
+    Validation { message: String, details: Option<Value> },
+    NotFound(String),
+    // The request clashes with the current state, such as a username that is taken
+    Conflict(String),
+    InsufficientFunds(String),
+    // The matching engine refused the command
+    EngineRejected(String),
+    // The database or the engine cannot take requests right now; retrying later may work
+    Unavailable(String),
+    // Logged in full; the client only learns that something went wrong
+    Internal(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<Value>,
+}
+
+impl AppError {
+    pub fn validation(message: impl Into<String>) -> Self {
+        AppError::Validation { message: message.into(), details: None }
+    }
+
+    // An error from the engine handle: either the engine refused the command or it is gone
+    pub fn engine(message: String) -> Self {
+        if message == sequencer::STOPPED {
+            AppError::Unavailable(message)
+        } else {
+            AppError::EngineRejected(message)
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Validation { .. } => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::InsufficientFunds(_) | AppError::EngineRejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        let (code, message, details) = match self {
+            AppError::Validation { message, details } => ("validation_failed", message.clone(), details.clone()),
+            AppError::NotFound(message) => ("not_found", message.clone(), None),
+            AppError::Conflict(message) => ("conflict", message.clone(), None),
+            AppError::InsufficientFunds(message) => ("insufficient_funds", message.clone(), None),
+            AppError::EngineRejected(message) => ("engine_rejected", message.clone(), None),
+            AppError::Unavailable(message) => ("unavailable", message.clone(), None),
+            AppError::Internal(_) => ("internal", "internal server error".to_string(), None),
+        };
+        ErrorBody { code, message, details }
+    }
+}
+
+// The full message, internal ones included, for logs
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Validation { message, .. }
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::InsufficientFunds(message)
+            | AppError::EngineRejected(message)
+            | AppError::Unavailable(message)
+            | AppError::Internal(message) => write!(f, "{}: {}", self.body().code, message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::Internal(message) = &self {
+            eprintln!("request failed: {}", message);
+        }
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+// Constraint violations are the client's doing; a lost connection or a transaction that
+// lost a race can be retried; anything else, including a row that does not convert, is ours
+impl From<tokio_postgres::Error> for AppError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        if e.is_closed() {
+            return AppError::Unavailable("the database connection was lost".to_string());
+        }
+        let Some(db_error) = e.as_db_error() else {
+            return AppError::Internal(e.to_string());
+        };
+
+        let code = db_error.code();
+        if *code == SqlState::UNIQUE_VIOLATION {
+            AppError::Conflict(match db_error.constraint() {
+                Some(constraint) => format!("already exists ({})", constraint),
+                None => "already exists".to_string(),
+            })
+        } else if *code == SqlState::FOREIGN_KEY_VIOLATION {
+            AppError::NotFound(db_error.detail().unwrap_or("a referenced record does not exist").to_string())
+        } else if *code == SqlState::CHECK_VIOLATION
+            || *code == SqlState::NOT_NULL_VIOLATION
+            || *code == SqlState::STRING_DATA_RIGHT_TRUNCATION
+            || *code == SqlState::NUMERIC_VALUE_OUT_OF_RANGE
+        {
+            AppError::validation(db_error.message())
+        } else if *code == SqlState::T_R_SERIALIZATION_FAILURE || *code == SqlState::T_R_DEADLOCK_DETECTED {
+            AppError::Conflict("the request clashed with another one; retry it".to_string())
+        } else if *code == SqlState::ADMIN_SHUTDOWN
+            || *code == SqlState::CANNOT_CONNECT_NOW
+            || *code == SqlState::TOO_MANY_CONNECTIONS
+        {
+            AppError::Unavailable("the database is not accepting requests".to_string())
+        } else {
+            AppError::Internal(e.to_string())
+        }
+    }
+}
+
+impl From<PoolError> for AppError {
+    fn from(e: PoolError) -> Self {
+        match e {
+            PoolError::Backend(e) => e.into(),
+            e => AppError::Unavailable(format!("no database connection: {}", e)),
+        }
+    }
+}
+
+// A request that failed validation, with what is wrong with each field as its details
+impl From<Vec<FieldError>> for AppError {
+    fn from(errors: Vec<FieldError>) -> Self {
+        AppError::Validation {
+            message: "the request has invalid fields".to_string(),
+            details: Some(serde_json::json!({ "fields": errors })),
+        }
+    }
+}
+
+// Such as the instrument registry lock, after a panic while it was held
+impl<T> From<PoisonError<T>> for AppError {
+    fn from(_: PoisonError<T>) -> Self {
+        AppError::Internal("a lock was poisoned by a panic".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_carry_their_status_and_a_stable_code() {
+        let error = AppError::InsufficientFunds("USD balance is 10".to_string());
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.body().code, "insufficient_funds");
+
+        let body = serde_json::to_value(AppError::validation("bad quantity").body()).unwrap();
+        assert_eq!(body, serde_json::json!({"code": "validation_failed", "message": "bad quantity", "details": null}));
+
+        // Internal messages stay in the log
+        let error = AppError::Internal("relation \"orders\" does not exist".to_string());
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.body().message, "internal server error");
+
+        let error = AppError::from(vec![FieldError { field: "quantity", message: "must be positive".to_string() }]);
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.body().details, Some(serde_json::json!({"fields": [{"field": "quantity", "message": "must be positive"}]})));
+
+        // No free connection in time is worth retrying
+        let error = AppError::from(PoolError::Timeout(deadpool_postgres::TimeoutType::Wait));
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        assert!(matches!(AppError::engine(sequencer::STOPPED.to_string()), AppError::Unavailable(_)));
+        assert!(matches!(AppError::engine("Market not found".to_string()), AppError::EngineRejected(_)));
+    }
+}
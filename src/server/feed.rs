@@ -0,0 +1,133 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::Response,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use super::AppState;
+use crate::matching_engine::engine::{MarketEvent, MarketUpdate};
+use crate::models::{InstrumentId, MarketDataMessage, MarketDataRequest, OrderSide, Ticker};
+
+// Clients send `{"action": "subscribe", "symbols": ["BTC/USD"]}` and get a snapshot of each
+// book followed by its updates, trades and ticker. `?depth=` limits the snapshot levels.
+pub async fn market_data_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let depth = params.get("depth").and_then(|d| d.parse::<usize>().ok()).unwrap_or(usize::MAX);
+    ws.on_upgrade(move |socket| stream_market_data(socket, state, depth))
+}
+
+async fn stream_market_data(mut socket: WebSocket, state: Arc<AppState>, depth: usize) {
+    let mut events = state.engine.subscribe_market_data();
+    // Subscribed markets and the last sequence number sent for each
+    let mut subscriptions: HashMap<InstrumentId, u64> = HashMap::new();
+
+    loop {
+        let replies = tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => handle_request(&state, &mut subscriptions, &text, depth).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = events.recv() => match event {
+                Ok(event) => forward_event(&mut subscriptions, event).into_iter().collect(),
+                // Updates were dropped; every subscribed book starts over from a new snapshot
+                Err(RecvError::Lagged(_)) => resubscribe(&state, &mut subscriptions, depth).await,
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        for reply in replies {
+            let Ok(text) = serde_json::to_string(&reply) else {
+                continue;
+            };
+            if socket.send(Message::Text(text)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_request(
+    state: &AppState,
+    subscriptions: &mut HashMap<InstrumentId, u64>,
+    text: &str,
+    depth: usize,
+) -> Vec<MarketDataMessage> {
+    match serde_json::from_str::<MarketDataRequest>(text) {
+        Ok(MarketDataRequest::Subscribe { symbols }) => {
+            let mut replies = Vec::new();
+            for symbol in symbols {
+                replies.extend(subscribe(state, subscriptions, symbol, depth).await);
+            }
+            replies
+        }
+        Ok(MarketDataRequest::Unsubscribe { symbols }) => {
+            for symbol in &symbols {
+                subscriptions.remove(symbol);
+            }
+            Vec::new()
+        }
+        Err(e) => vec![MarketDataMessage::Error { message: e.to_string() }],
+    }
+}
+
+// Events already queued for the receiver that are not newer than the snapshot are skipped
+async fn subscribe(
+    state: &AppState,
+    subscriptions: &mut HashMap<InstrumentId, u64>,
+    instrument: InstrumentId,
+    depth: usize,
+) -> Option<MarketDataMessage> {
+    if !state.instruments.read().ok()?.contains(&instrument) {
+        return Some(MarketDataMessage::Error { message: format!("Unknown symbol {}", instrument) });
+    }
+    let (sequence, book) = state.engine.snapshot(instrument.clone(), depth).await.ok()?;
+    subscriptions.insert(instrument, sequence);
+    Some(MarketDataMessage::Snapshot { sequence, book })
+}
+
+async fn resubscribe(
+    state: &AppState,
+    subscriptions: &mut HashMap<InstrumentId, u64>,
+    depth: usize,
+) -> Vec<MarketDataMessage> {
+    let instruments: Vec<InstrumentId> = subscriptions.keys().cloned().collect();
+    let mut replies = Vec::new();
+    for instrument in instruments {
+        replies.extend(subscribe(state, subscriptions, instrument, depth).await);
+    }
+    replies
+}
+
+fn forward_event(subscriptions: &mut HashMap<InstrumentId, u64>, event: MarketEvent) -> Option<MarketDataMessage> {
+    let last_sequence = subscriptions.get_mut(&event.instrument)?;
+    if event.sequence <= *last_sequence {
+        return None;
+    }
+    *last_sequence = event.sequence;
+
+    let symbol = event.instrument;
+    let sequence = event.sequence;
+    Some(match event.update {
+        MarketUpdate::Levels { bids, asks } => MarketDataMessage::Update { symbol, sequence, bids, asks },
+        MarketUpdate::Trade(fill) => MarketDataMessage::Trade {
+            symbol,
+            sequence,
+            price: fill.price,
+            quantity: fill.size,
+            aggressor_side: OrderSide::from(fill.aggressor),
+            timestamp: fill.timestamp,
+        },
+        MarketUpdate::Ticker { price, timestamp } => MarketDataMessage::Ticker {
+            sequence,
+            ticker: Ticker { symbol, price, timestamp },
+        },
+    })
+}
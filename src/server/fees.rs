@@ -0,0 +1,123 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use crate::database::{fee_tiers, trade_fees, trades, Transaction};
+use super::{balances, ledger};
+use super::error::AppError;
+use crate::matching_engine::orderbook::Fill;
+use crate::models::{FeeTier, InstrumentId, LedgerEntryKind, Liquidity};
+
+// Tiers in `fee_tiers` with this symbol apply to every symbol that has none of its own
+pub const DEFAULT_FEE_SYMBOL: &str = "*";
+
+// Fees are charged over this many trailing days of a user's traded notional
+const VOLUME_WINDOW_DAYS: i32 = 30;
+
+#[derive(Debug, Clone, Default)]
+pub struct FeeSchedule {
+    tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    pub fn new(mut tiers: Vec<FeeTier>) -> Self {
+        tiers.sort_by_key(|tier| tier.min_volume);
+        FeeSchedule { tiers }
+    }
+
+    // The highest tier the volume qualifies for; no tier means no fee
+    pub fn rate(&self, liquidity: Liquidity, volume: Decimal) -> Decimal {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| volume >= tier.min_volume)
+            .map_or(Decimal::ZERO, |tier| match liquidity {
+                Liquidity::Maker => tier.maker_rate,
+                Liquidity::Taker => tier.taker_rate,
+            })
+    }
+
+    // The most a trade of `notional` can be charged, whatever the volume and liquidity;
+    // buys reserve it along with what they pay
+    pub fn max_fee(&self, notional: Decimal) -> Decimal {
+        let rate = self
+            .tiers
+            .iter()
+            .map(|tier| tier.maker_rate.max(tier.taker_rate))
+            .max()
+            .unwrap_or_default();
+        (notional * rate).round_dp_with_strategy(8, RoundingStrategy::AwayFromZero)
+    }
+}
+
+pub fn fee_for(notional: Decimal, rate: Decimal) -> Decimal {
+    (notional * rate).round_dp(8)
+}
+
+// The symbol's own tiers, or the default tiers when it has none
+pub async fn load_schedule(tx: &Transaction<'_>, symbol: &InstrumentId) -> Result<FeeSchedule, tokio_postgres::Error> {
+    Ok(FeeSchedule::new(fee_tiers::for_symbol(tx, symbol, DEFAULT_FEE_SYMBOL).await?))
+}
+
+// Charges the maker and the taker of a recorded trade, each at the rate of their own
+// volume tier, in the quote asset, and records the fees against the trade. The buyer pays
+// out of what their order reserved for fees, the seller out of what the trade paid them.
+// Run it after `settle_fill`.
+pub async fn charge_fill(
+    tx: &Transaction<'_>,
+    schedule: &FeeSchedule,
+    trade_id: i32,
+    symbol: &InstrumentId,
+    fill: &Fill,
+) -> Result<(), AppError> {
+    let notional = fill.price * fill.size;
+    let sides = [
+        (fill.maker_user_id, fill.maker_order_id, Liquidity::Maker),
+        (fill.taker_user_id, fill.taker_order_id, Liquidity::Taker),
+    ];
+
+    for (user_id, order_id, liquidity) in sides {
+        let volume = trades::trailing_volume(tx, user_id, symbol, VOLUME_WINDOW_DAYS).await?;
+        let rate = schedule.rate(liquidity, volume);
+        let fee = fee_for(notional, rate);
+
+        trade_fees::insert(tx, trade_id, user_id, order_id, liquidity, rate, fee).await?;
+        if fee.is_zero() {
+            continue;
+        }
+        if order_id == fill.buy_order_id() {
+            balances::spend(tx, order_id, user_id, symbol.quote(), fee).await?;
+        } else {
+            balances::credit(tx, user_id, symbol.quote(), -fee).await?;
+        }
+        let postings = [
+            ledger::Posting::new(ledger::Account::User(user_id), symbol.quote(), -fee),
+            ledger::Posting::new(ledger::Account::Fees, symbol.quote(), fee),
+        ];
+        ledger::post(tx, LedgerEntryKind::Fee, Some(trade_id), None, &postings).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn volume_picks_the_highest_tier_reached() {
+        let schedule = FeeSchedule::new(vec![
+            FeeTier { min_volume: dec!(100000), maker_rate: dec!(0.0008), taker_rate: dec!(0.0016) },
+            FeeTier { min_volume: dec!(0), maker_rate: dec!(0.001), taker_rate: dec!(0.002) },
+        ]);
+
+        assert_eq!(schedule.rate(Liquidity::Maker, dec!(0)), dec!(0.001));
+        assert_eq!(schedule.rate(Liquidity::Taker, dec!(99999.99)), dec!(0.002));
+        assert_eq!(schedule.rate(Liquidity::Taker, dec!(100000)), dec!(0.0016));
+        assert_eq!(FeeSchedule::default().rate(Liquidity::Taker, dec!(100000)), dec!(0));
+
+        assert_eq!(fee_for(dec!(12345.678), dec!(0.0016)), dec!(19.7530848));
+
+        // Reserved at the highest rate of any tier, rounded up
+        assert_eq!(schedule.max_fee(dec!(1000)), dec!(2));
+        assert_eq!(schedule.max_fee(dec!(0.000000001)), dec!(0.00000001));
+        assert_eq!(FeeSchedule::default().max_fee(dec!(1000)), dec!(0));
+    }
+}
@@ -1,15 +1,26 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
     response::Json,
 };
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OwnedRwLockReadGuard;
 use rust_decimal::Decimal;
-use chrono::{DateTime, Utc};
-use super::AppState;
+use chrono::Utc;
+use super::{balances, fees, instruments, ledger, risk, settlement, valuation, AppState};
+use super::error::AppError;
+use super::instruments::InstrumentViolation;
+use crate::database::{market_data, orders, positions, trades, users, Client, Database, GenericClient};
 use crate::models::*;
+use crate::matching_engine::engine::{Execution, TriggeredStop};
+use crate::matching_engine::orderbook::{BidOrAsk, Fill, Order as EngineOrder};
+
+// How long writing a matched order's outcome waits before trying again while the database
+// is unavailable, doubling up to the maximum
+const RECORD_RETRY_DELAY: Duration = Duration::from_millis(500);
+const RECORD_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
 
 #[derive(Serialize)]
 pub struct HealthResponse {
@@ -20,7 +31,7 @@ pub struct HealthResponse {
 // Health check endpoint
 pub async fn health_check(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<HealthResponse>, StatusCode> {
+) -> Result<Json<HealthResponse>, AppError> {
     let db_status = match state.db.test_connection().await {
         Ok(_) => "connected".to_string(),
         Err(_) => "disconnected".to_string(),
@@ -32,31 +43,47 @@ pub async fn health_check(
     }))
 }
 
+// Waits a bounded time for a pooled connection; when none can be had the database is unavailable
+async fn checkout(state: &AppState) -> Result<Client, AppError> {
+    Ok(state.db.get().await?)
+}
+
+fn user_not_found(user_id: i32) -> AppError {
+    AppError::NotFound(format!("user {} does not exist", user_id))
+}
+
+fn market_not_found(symbol: &InstrumentId) -> AppError {
+    AppError::NotFound(format!("no market trades {}", symbol))
+}
+
+fn open_order_not_found(order_id: i32) -> AppError {
+    AppError::NotFound(format!("order {} is not open", order_id))
+}
+
+async fn require_user(client: &impl GenericClient, user_id: i32) -> Result<(), AppError> {
+    if !users::exists(client, user_id).await? {
+        return Err(user_not_found(user_id));
+    }
+    Ok(())
+}
+
 // User management endpoints
 pub async fn create_user(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<CreateUserRequest>,
-) -> Result<Json<User>, StatusCode> {
-    let client = state.db.get_client();
+) -> Result<Json<User>, AppError> {
+    payload.validate()?;
     let initial_balance = payload.initial_balance.unwrap_or_default();
     
-    let row = client
-        .query_one(
-            "INSERT INTO users (username, cash_balance) VALUES ($1, $2) RETURNING user_id, username, cash_balance, realized_pnl, unrealized_pnl, created_at, updated_at",
-            &[&payload.username, &initial_balance.to_string()],
-        )
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let user = User {
-        user_id: row.get(0),
-        username: row.get(1),
-        cash_balance: row.get::<_, String>(2).parse().unwrap_or_default(),
-        realized_pnl: row.get::<_, String>(3).parse().unwrap_or_default(),
-        unrealized_pnl: row.get::<_, String>(4).parse().unwrap_or_default(),
-        created_at: row.get(5),
-        updated_at: row.get(6),
-    };
+    let mut client = checkout(&state).await?;
+    let tx = client.transaction().await?;
+    let mut user = users::insert(&tx, &payload.username).await?;
+
+    if initial_balance > Decimal::ZERO {
+        user.cash_balance = balances::credit(&tx, user.user_id, EQUITY_CURRENCY, initial_balance).await?.available;
+        ledger::post_transfer(&tx, LedgerEntryKind::Deposit, user.user_id, EQUITY_CURRENCY, initial_balance).await?;
+    }
+    tx.commit().await?;
 
     Ok(Json(user))
 }
@@ -64,26 +91,11 @@ pub async fn create_user(
 pub async fn get_user(
     State(state): State<Arc<AppState>>,
     Path(user_id): Path<i32>,
-) -> Result<Json<User>, StatusCode> {
-    let client = state.db.get_client();
-    
-    let row = client
-        .query_one(
-            "SELECT user_id, username, cash_balance, realized_pnl, unrealized_pnl, created_at, updated_at FROM users WHERE user_id = $1",
-            &[&user_id],
-        )
-        .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
-
-    let user = User {
-        user_id: row.get(0),
-        username: row.get(1),
-        cash_balance: row.get::<_, String>(2).parse().unwrap_or_default(),
-        realized_pnl: row.get::<_, String>(3).parse().unwrap_or_default(),
-        unrealized_pnl: row.get::<_, String>(4).parse().unwrap_or_default(),
-        created_at: row.get(5),
-        updated_at: row.get(6),
-    };
+) -> Result<Json<User>, AppError> {
+    let client = checkout(&state).await?;
+    let user = users::find(&client, user_id)
+        .await?
+        .ok_or_else(|| user_not_found(user_id))?;
 
     Ok(Json(user))
 }
@@ -91,188 +103,383 @@ pub async fn get_user(
 pub async fn get_user_profile(
     State(state): State<Arc<AppState>>,
     Path(user_id): Path<i32>,
-) -> Result<Json<UserProfile>, StatusCode> {
-    let client = state.db.get_client();
-    
-    // Get user details
-    let user_row = client
-        .query_one(
-            "SELECT user_id, username, cash_balance, realized_pnl, unrealized_pnl FROM users WHERE user_id = $1",
-            &[&user_id],
-        )
-        .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
-
-    // Get user positions
-    let position_rows = client
-        .query(
-            "SELECT position_id, user_id, symbol, quantity, avg_cost, updated_at FROM positions WHERE user_id = $1",
-            &[&user_id],
-        )
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let positions: Vec<Position> = position_rows
-        .iter()
-        .map(|row| Position {
-            position_id: row.get(0),
-            user_id: row.get(1),
-            symbol: row.get(2),
-            quantity: row.get::<_, String>(3).parse().unwrap_or_default(),
-            avg_cost: row.get::<_, String>(4).parse().unwrap_or_default(),
-            updated_at: row.get(5),
-        })
-        .collect();
+) -> Result<Json<UserProfile>, AppError> {
+    let client = checkout(&state).await?;
+    let user = users::find(&client, user_id)
+        .await?
+        .ok_or_else(|| user_not_found(user_id))?;
+    let positions = positions::for_user(&client, user_id).await?;
+    let balances = balances::load_balances(&client, user_id).await?;
 
     let profile = UserProfile {
-        user_id: user_row.get(0),
-        username: user_row.get(1),
-        cash_balance: user_row.get::<_, String>(2).parse().unwrap_or_default(),
-        realized_pnl: user_row.get::<_, String>(3).parse().unwrap_or_default(),
-        unrealized_pnl: user_row.get::<_, String>(4).parse().unwrap_or_default(),
+        user_id: user.user_id,
+        username: user.username,
+        cash_balance: user.cash_balance,
+        realized_pnl: user.realized_pnl,
+        unrealized_pnl: user.unrealized_pnl,
+        balances,
         positions,
     };
 
     Ok(Json(profile))
 }
 
+// Positions marked to market; see `valuation` for where the marks come from
+pub async fn get_user_positions(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i32>,
+) -> Result<Json<Vec<PositionSummary>>, AppError> {
+    let client = checkout(&state).await?;
+    require_user(&client, user_id).await?;
+
+    let positions = valuation::position_summaries(&client, user_id).await?;
+
+    Ok(Json(positions))
+}
+
+pub async fn get_user_balances(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i32>,
+) -> Result<Json<Vec<Balance>>, AppError> {
+    let client = checkout(&state).await?;
+    require_user(&client, user_id).await?;
+
+    let balances = balances::load_balances(&client, user_id).await?;
+
+    Ok(Json(balances))
+}
+
+pub async fn deposit(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i32>,
+    Json(payload): Json<TransferRequest>,
+) -> Result<Json<Balance>, AppError> {
+    let asset = transfer_asset(&state, &payload)?;
+
+    let mut client = checkout(&state).await?;
+    let tx = client.transaction().await?;
+    require_user(&tx, user_id).await?;
+    let balance = balances::credit(&tx, user_id, &asset, payload.amount).await?;
+    ledger::post_transfer(&tx, LedgerEntryKind::Deposit, user_id, &asset, payload.amount).await?;
+    tx.commit().await?;
+
+    Ok(Json(balance))
+}
+
+// Only the available balance can be withdrawn; what open orders lock stays put
+pub async fn withdraw(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i32>,
+    Json(payload): Json<TransferRequest>,
+) -> Result<Json<Balance>, AppError> {
+    let asset = transfer_asset(&state, &payload)?;
+
+    let mut client = checkout(&state).await?;
+    let tx = client.transaction().await?;
+    require_user(&tx, user_id).await?;
+    let balance = balances::withdraw(&tx, user_id, &asset, payload.amount)
+        .await?
+        .ok_or_else(|| AppError::InsufficientFunds(format!("less than {} {} is available", payload.amount, asset)))?;
+    ledger::post_transfer(&tx, LedgerEntryKind::Withdrawal, user_id, &asset, payload.amount).await?;
+    tx.commit().await?;
+
+    Ok(Json(balance))
+}
+
+// Every posting to the user's accounts with the running balance after it, optionally for one asset
+pub async fn get_user_statement(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i32>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<StatementLine>>, AppError> {
+    let client = checkout(&state).await?;
+    require_user(&client, user_id).await?;
+
+    let asset = params.get("asset").map(|asset| asset.to_uppercase());
+    let lines = ledger::statement(&client, user_id, asset.as_deref()).await?;
+
+    Ok(Json(lines))
+}
+
+// Transfers move a positive amount of an asset that some registered market trades
+fn transfer_asset(state: &AppState, payload: &TransferRequest) -> Result<String, AppError> {
+    if payload.amount <= Decimal::ZERO {
+        return Err(AppError::validation("the amount must be positive"));
+    }
+    balance_change_asset(state, &payload.asset, payload.amount)
+}
+
+// Anything posted to a balance has to be in an asset some registered market trades, and fit
+// the balance columns exactly rather than be rounded by Postgres
+fn balance_change_asset(state: &AppState, asset: &str, amount: Decimal) -> Result<String, AppError> {
+    let asset = asset.to_uppercase();
+    let traded = state
+        .instruments
+        .read()?
+        .trades_asset(&asset);
+    if !traded {
+        return Err(AppError::validation(format!("no market trades {}", asset)));
+    }
+    if let Some(message) = column_overflow(amount) {
+        return Err(AppError::validation(format!("the amount {}", message)));
+    }
+    Ok(asset)
+}
+
 // Order management endpoints
 pub async fn create_order(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<CreateOrderRequest>,
-) -> Result<Json<Order>, StatusCode> {
-    let client = state.db.get_client();
-    
+) -> Result<Json<OrderExecution>, AppError> {
+    payload.validate()?;
     let time_in_force = payload.time_in_force.unwrap_or(TimeInForce::GTC);
-    
-    let row = client
-        .query_one(
-            "INSERT INTO orders (user_id, symbol, side, order_type, quantity, limit_price, remaining_quantity, time_in_force) 
-             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) 
-             RETURNING order_id, user_id, symbol, side, order_type, quantity, limit_price, filled_quantity, remaining_quantity, status, time_in_force, submission_time, updated_at",
-            &[
-                &payload.user_id, 
-                &payload.symbol, 
-                &payload.side.to_string(), 
-                &payload.order_type.to_string(), 
-                &payload.quantity.to_string(), 
-                &payload.limit_price.map(|p| p.to_string()),
-                &payload.quantity.to_string(),
-                &time_in_force.to_string()
-            ],
-        )
+
+    // Only registered symbols trade
+    let instrument = state
+        .instruments
+        .read()?
+        .get(&payload.symbol)
+        .cloned()
+        .ok_or_else(|| market_not_found(&payload.symbol))?;
+
+    let quantity = payload.quantity;
+    let market_cost = state
+        .engine
+        .cost_to_fill(payload.symbol.clone(), BidOrAsk::from(&payload.side), quantity)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let order = Order {
-        order_id: row.get(0),
-        user_id: row.get(1),
-        symbol: row.get(2),
-        side: match row.get::<_, String>(3).as_str() {
-            "buy" => OrderSide::Buy,
-            "sell" => OrderSide::Sell,
-            _ => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-        },
-        order_type: match row.get::<_, String>(4).as_str() {
-            "limit" => OrderType::Limit,
-            "market" => OrderType::Market,
-            "stop" => OrderType::Stop,
-            _ => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-        },
-        quantity: row.get::<_, String>(5).parse().unwrap_or_default(),
-        limit_price: row.get::<_, Option<String>>(6).and_then(|p| p.parse().ok()),
-        filled_quantity: row.get::<_, String>(7).parse().unwrap_or_default(),
-        remaining_quantity: row.get::<_, String>(8).parse().unwrap_or_default(),
-        status: match row.get::<_, String>(9).as_str() {
-            "pending" => OrderStatus::Pending,
-            "active" => OrderStatus::Active,
-            "filled" => OrderStatus::Filled,
-            "cancelled" => OrderStatus::Cancelled,
-            "rejected" => OrderStatus::Rejected,
-            _ => OrderStatus::Pending,
-        },
-        time_in_force: match row.get::<_, String>(10).as_str() {
-            "GTC" => TimeInForce::GTC,
-            "IOC" => TimeInForce::IOC,
-            "FOK" => TimeInForce::FOK,
-            "DAY" => TimeInForce::DAY,
-            _ => TimeInForce::GTC,
-        },
-        submission_time: row.get(11),
-        updated_at: row.get(12),
+        .map_err(AppError::engine)?;
+
+    // Market orders are priced against the book as it stands and stop orders at their stop price
+    let notional = match payload.order_type {
+        OrderType::Market => market_cost,
+        OrderType::Stop => quantity * payload.stop_price.unwrap_or_default(),
+        OrderType::Limit | OrderType::StopLimit => quantity * payload.limit_price.unwrap_or_default(),
     };
 
-    Ok(Json(order))
+    // Orders that break the instrument's constraints are refused outright
+    let prices: Vec<Decimal> = payload.limit_price.into_iter().chain(payload.stop_price).collect();
+    instruments::validate_order(&instrument, quantity, &prices, notional).map_err(|violation| match violation {
+        InstrumentViolation::MarketHalted => AppError::Conflict(format!("{} is halted", instrument.symbol)),
+        violation => AppError::validation(violation.to_string()),
+    })?;
+
+    // Pre-trade checks; a rejected order is still recorded with its reason. An accepted one
+    // locks what it may spend in the same transaction, so no other order can spend it too.
+    let settling = state.settling.clone().read_owned().await;
+    let mut client = checkout(&state).await?;
+    let tx = client.transaction().await?;
+    let exposure = risk::load_exposure(&tx, payload.user_id, &payload.symbol)
+        .await?
+        .ok_or_else(|| user_not_found(payload.user_id))?;
+    let max_fee = fees::load_schedule(&tx, &payload.symbol).await?.max_fee(notional);
+    let reject_reason = risk::check_order(&payload.side, quantity, notional, max_fee, &exposure).err();
+    let status = match reject_reason {
+        Some(_) => OrderStatus::Rejected,
+        None => OrderStatus::Pending,
+    };
+    
+    let order = orders::insert(&tx, &payload, time_in_force, status, reject_reason).await?;
+
+    if reject_reason.is_none() {
+        let amount = risk::lock_amount(&payload.side, quantity, notional, max_fee);
+        let asset = balances::locked_asset(&payload.symbol, &payload.side);
+        balances::lock(&tx, order.order_id, payload.user_id, asset, amount).await?;
+    }
+    tx.commit().await?;
+    // The connection goes back to the pool while the engine matches
+    drop(client);
+
+    if order.reject_reason.is_some() {
+        return Ok(Json(OrderExecution { order, fills: Vec::new() }));
+    }
+    // A buy with no limit price is held to what it locked, however the book moves before it trades
+    let budget = (payload.side == OrderSide::Buy && !payload.order_type.has_limit_price()).then_some(notional);
+    Ok(Json(execute_order(&state, order, budget, settling).await?))
+}
+
+// Matches an order that is recorded and holds its lock, then writes what came of it.
+// `budget` caps what a buy may pay in total. `settling` is held until the outcome is written.
+pub(super) async fn execute_order(
+    state: &AppState,
+    order: Order,
+    budget: Option<Decimal>,
+    settling: OwnedRwLockReadGuard<()>,
+) -> Result<OrderExecution, AppError> {
+    let execution = match match_order(state, &order, budget).await {
+        Ok(execution) => execution,
+        // An engine that has stopped may have matched the order before it went; it stays
+        // pending for recovery to settle from the journal
+        Err(e @ AppError::Unavailable(_)) => return Err(e),
+        Err(e) => {
+            if let Err(reject_error) = reject_unmatched(state, &order).await {
+                eprintln!("failed to reject order {} the engine refused: {:?}", order.order_id, reject_error);
+            }
+            return Err(e);
+        }
+    };
+
+    match write_execution(&state.db, &order, &execution).await {
+        // The engine cannot take the match back, so its outcome is written once the database is back
+        Err(AppError::Unavailable(message)) => {
+            let order_id = order.order_id;
+            tokio::spawn(write_when_available(state.db.clone(), order, execution, settling));
+            Err(AppError::Unavailable(format!(
+                "order {} was matched but its outcome is not recorded yet ({}); it will be once the database is back",
+                order_id, message
+            )))
+        }
+        Err(e) => {
+            eprintln!("failed to record the outcome of order {}: {:?} {:?}", order.order_id, e, execution);
+            Err(e)
+        }
+        result => result,
+    }
+}
+
+// An order the engine refused never traded; it is rejected and its lock returned
+async fn reject_unmatched(state: &AppState, order: &Order) -> Result<(), AppError> {
+    let mut client = checkout(state).await?;
+    let tx = client.transaction().await?;
+    orders::reject(&tx, order.order_id, RejectReason::EngineRejected).await?;
+    balances::release_finished(&tx, &[order.order_id]).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn write_execution(db: &Database, order: &Order, execution: &Execution) -> Result<OrderExecution, AppError> {
+    let mut client = db.get().await?;
+    record_execution(&mut client, order, execution).await
+}
+
+async fn write_when_available(db: Database, order: Order, execution: Execution, settling: OwnedRwLockReadGuard<()>) {
+    let mut delay = RECORD_RETRY_DELAY;
+    loop {
+        tokio::time::sleep(delay).await;
+        match write_execution(&db, &order, &execution).await {
+            Ok(_) => break,
+            Err(AppError::Unavailable(_)) => delay = (delay * 2).min(RECORD_RETRY_MAX_DELAY),
+            Err(e) => {
+                eprintln!("gave up recording the outcome of order {}: {:?} {:?}", order.order_id, e, execution);
+                break;
+            }
+        }
+    }
+    drop(settling);
+}
+
+// Writes what matching `order`, as it was recorded before, produced: the trades and their
+// settlement, the orders they touched and the order itself, together or not at all
+pub(super) async fn record_execution(client: &mut Client, order: &Order, execution: &Execution) -> Result<OrderExecution, AppError> {
+    let mut order = order.clone();
+    let tx = client.transaction().await?;
+    let fee_schedule = fees::load_schedule(&tx, &order.symbol).await?;
+
+    let mut trades = Vec::with_capacity(execution.fills.len());
+    // Orders whose status may have become final here, so their remaining locks are released
+    let mut touched = vec![order.order_id];
+    for fill in &execution.fills {
+        touched.push(fill.maker_order_id);
+        touched.push(fill.taker_order_id);
+        let trade_id = trades::insert(&tx, &order.symbol, fill).await?;
+
+        // The incoming order's own row is written once all its fills are counted; it can be
+        // the maker when a stop it set off trades against its resting remainder
+        if fill.maker_order_id != order.order_id {
+            orders::fill_resting(&tx, fill.maker_order_id, fill.size).await?;
+        }
+        if fill.taker_order_id != order.order_id {
+            // A stop order triggered by this one; its status is settled below
+            orders::fill(&tx, fill.taker_order_id, fill.size).await?;
+        }
+
+        settlement::settle_fill(&tx, trade_id, &order.symbol, fill).await?;
+        fees::charge_fill(&tx, &fee_schedule, trade_id, &order.symbol, fill).await?;
+
+        trades.push(fill.to_trade(trade_id, &order.symbol));
+    }
+
+    for stop in execution.triggered.iter().filter(|stop| stop.order_id != order.order_id) {
+        touched.push(stop.order_id);
+        orders::settle_triggered(&tx, stop.order_id, stop.resting).await?;
+    }
+
+    let filled = settle_incoming(&mut order, &execution.fills, &execution.triggered);
+    let order = orders::save_execution(&tx, &order, filled).await?;
+
+    balances::release_finished(&tx, &touched).await?;
+    tx.commit().await?;
+
+    Ok(OrderExecution { order, fills: trades })
+}
+
+// Where the incoming order stands once matching is over, counting its fills on either side;
+// returns the size they add up to
+fn settle_incoming(order: &mut Order, fills: &[Fill], triggered: &[TriggeredStop]) -> Decimal {
+    let filled = fills
+        .iter()
+        .filter(|fill| fill.taker_order_id == order.order_id || fill.maker_order_id == order.order_id)
+        .map(|fill| fill.size)
+        .sum::<Decimal>();
+    order.filled_quantity += filled;
+    order.remaining_quantity = order.quantity - order.filled_quantity;
+
+    let triggered = triggered.iter().find(|stop| stop.order_id == order.order_id);
+    let rests = match triggered {
+        Some(stop) => stop.resting,
+        None => order.order_type == OrderType::Limit && order.time_in_force.can_rest(),
+    };
+    order.status = if order.remaining_quantity <= Decimal::ZERO {
+        OrderStatus::Filled
+    } else if order.order_type.is_stop() && triggered.is_none() {
+        OrderStatus::Pending
+    } else if order.time_in_force == TimeInForce::FOK {
+        // A FOK order that could not fill completely never traded at all
+        order.reject_reason = Some(RejectReason::FillOrKillUnfilled);
+        OrderStatus::Rejected
+    } else if rests {
+        OrderStatus::Active
+    } else {
+        // Whatever a market or IOC order could not fill is not kept on the book
+        OrderStatus::Cancelled
+    };
+    filled
+}
+
+// Hands a persisted order to the matching engine
+async fn match_order(state: &AppState, order: &Order, budget: Option<Decimal>) -> Result<Execution, AppError> {
+    let instrument = order.symbol.clone();
+    let mut engine_order = EngineOrder::with_id(order.order_id, order.user_id, BidOrAsk::from(&order.side), order.quantity)
+        .with_time_in_force(order.time_in_force);
+    if let Some(budget) = budget {
+        engine_order = engine_order.with_budget(budget);
+    }
+
+    let engine = &state.engine;
+    let execution = match (order.order_type, order.limit_price, order.stop_price) {
+        (OrderType::Market, _, _) => engine.place_market_order(instrument, engine_order).await,
+        (OrderType::Limit, Some(price), _) => engine.place_limit_order(instrument, price, engine_order).await,
+        (OrderType::Stop, _, Some(stop_price)) => engine.place_stop_order(instrument, stop_price, None, engine_order).await,
+        (OrderType::StopLimit, Some(price), Some(stop_price)) => {
+            engine.place_stop_order(instrument, stop_price, Some(price), engine_order).await
+        }
+        _ => return Err(AppError::validation(format!("{} orders need their prices", order.order_type))),
+    };
+    execution.map_err(AppError::engine)
 }
 
 pub async fn get_orders(
     State(state): State<Arc<AppState>>,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<Json<Vec<Order>>, StatusCode> {
-    let client = state.db.get_client();
-    let symbol = params.get("symbol");
+) -> Result<Json<Vec<Order>>, AppError> {
+    let client = checkout(&state).await?;
+    let symbol = params
+        .get("symbol")
+        .map(|symbol| symbol.parse::<InstrumentId>())
+        .transpose()
+        .map_err(AppError::validation)?;
     let user_id = params.get("user_id").and_then(|id| id.parse::<i32>().ok());
 
-    let query_str = match (symbol, user_id) {
-        (Some(_), Some(_)) => 
-            "SELECT order_id, user_id, symbol, side, order_type, quantity, limit_price, filled_quantity, remaining_quantity, status, time_in_force, submission_time, updated_at FROM orders WHERE symbol = $1 AND user_id = $2 ORDER BY submission_time DESC",
-        (Some(_), None) => 
-            "SELECT order_id, user_id, symbol, side, order_type, quantity, limit_price, filled_quantity, remaining_quantity, status, time_in_force, submission_time, updated_at FROM orders WHERE symbol = $1 ORDER BY submission_time DESC",
-        (None, Some(_)) => 
-            "SELECT order_id, user_id, symbol, side, order_type, quantity, limit_price, filled_quantity, remaining_quantity, status, time_in_force, submission_time, updated_at FROM orders WHERE user_id = $1 ORDER BY submission_time DESC",
-        (None, None) => 
-            "SELECT order_id, user_id, symbol, side, order_type, quantity, limit_price, filled_quantity, remaining_quantity, status, time_in_force, submission_time, updated_at FROM orders ORDER BY submission_time DESC LIMIT 100",
-    };
-
-    let rows = match (symbol, user_id) {
-        (Some(sym), Some(uid)) => client.query(query_str, &[&sym, &uid]).await,
-        (Some(sym), None) => client.query(query_str, &[&sym]).await,
-        (None, Some(uid)) => client.query(query_str, &[&uid]).await,
-        (None, None) => client.query(query_str, &[]).await,
-    }.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let orders: Vec<Order> = rows
-        .iter()
-        .map(|row| {
-            Order {
-                order_id: row.get(0),
-                user_id: row.get(1),
-                symbol: row.get(2),
-                side: match row.get::<_, String>(3).as_str() {
-                    "buy" => OrderSide::Buy,
-                    "sell" => OrderSide::Sell,
-                    _ => OrderSide::Buy,
-                },
-                order_type: match row.get::<_, String>(4).as_str() {
-                    "limit" => OrderType::Limit,
-                    "market" => OrderType::Market,
-                    "stop" => OrderType::Stop,
-                    _ => OrderType::Limit,
-                },
-                quantity: row.get::<_, String>(5).parse().unwrap_or_default(),
-                limit_price: row.get::<_, Option<String>>(6).and_then(|p| p.parse().ok()),
-                filled_quantity: row.get::<_, String>(7).parse().unwrap_or_default(),
-                remaining_quantity: row.get::<_, String>(8).parse().unwrap_or_default(),
-                status: match row.get::<_, String>(9).as_str() {
-                    "pending" => OrderStatus::Pending,
-                    "active" => OrderStatus::Active,
-                    "filled" => OrderStatus::Filled,
-                    "cancelled" => OrderStatus::Cancelled,
-                    "rejected" => OrderStatus::Rejected,
-                    _ => OrderStatus::Pending,
-                },
-                time_in_force: match row.get::<_, String>(10).as_str() {
-                    "GTC" => TimeInForce::GTC,
-                    "IOC" => TimeInForce::IOC,
-                    "FOK" => TimeInForce::FOK,
-                    "DAY" => TimeInForce::DAY,
-                    _ => TimeInForce::GTC,
-                },
-                submission_time: row.get(11),
-                updated_at: row.get(12),
-            }
-        })
-        .collect();
+    let orders = orders::find(&client, symbol.as_ref(), user_id).await?;
 
     Ok(Json(orders))
 }
@@ -280,49 +487,30 @@ pub async fn get_orders(
 pub async fn cancel_order(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<CancelOrderRequest>,
-) -> Result<Json<Order>, StatusCode> {
-    let client = state.db.get_client();
-    
-    let row = client
-        .query_one(
-            "UPDATE orders SET status = 'cancelled', updated_at = CURRENT_TIMESTAMP 
-             WHERE order_id = $1 AND user_id = $2 AND status IN ('pending', 'active')
-             RETURNING order_id, user_id, symbol, side, order_type, quantity, limit_price, filled_quantity, remaining_quantity, status, time_in_force, submission_time, updated_at",
-            &[&payload.order_id, &payload.user_id],
-        )
+) -> Result<Json<Order>, AppError> {
+    let symbol = orders::open_symbol(&checkout(&state).await?, payload.order_id, payload.user_id)
+        .await?
+        .ok_or_else(|| open_order_not_found(payload.order_id))?;
+
+    // Pull the order off the book, or out of the trigger book, first so it cannot trade after
+    // being marked cancelled. An order the engine does not hold, such as one still being
+    // matched, stays open.
+    state
+        .engine
+        .cancel_order(symbol, payload.order_id)
         .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
-
-    let order = Order {
-        order_id: row.get(0),
-        user_id: row.get(1),
-        symbol: row.get(2),
-        side: match row.get::<_, String>(3).as_str() {
-            "buy" => OrderSide::Buy,
-            "sell" => OrderSide::Sell,
-            _ => OrderSide::Buy,
-        },
-        order_type: match row.get::<_, String>(4).as_str() {
-            "limit" => OrderType::Limit,
-            "market" => OrderType::Market,
-            "stop" => OrderType::Stop,
-            _ => OrderType::Limit,
-        },
-        quantity: row.get::<_, String>(5).parse().unwrap_or_default(),
-        limit_price: row.get::<_, Option<String>>(6).and_then(|p| p.parse().ok()),
-        filled_quantity: row.get::<_, String>(7).parse().unwrap_or_default(),
-        remaining_quantity: row.get::<_, String>(8).parse().unwrap_or_default(),
-        status: OrderStatus::Cancelled,
-        time_in_force: match row.get::<_, String>(10).as_str() {
-            "GTC" => TimeInForce::GTC,
-            "IOC" => TimeInForce::IOC,
-            "FOK" => TimeInForce::FOK,
-            "DAY" => TimeInForce::DAY,
-            _ => TimeInForce::GTC,
-        },
-        submission_time: row.get(11),
-        updated_at: row.get(12),
-    };
+        .map_err(AppError::engine)?;
+
+    // Fills it made before it left the book may still be being written; its lock pays for
+    // them, so it is released only once they are
+    let _settled = state.settling.write().await;
+    let mut client = checkout(&state).await?;
+    let tx = client.transaction().await?;
+    let order = orders::cancel(&tx, payload.order_id, payload.user_id)
+        .await?
+        .ok_or_else(|| open_order_not_found(payload.order_id))?;
+    balances::release_finished(&tx, &[payload.order_id]).await?;
+    tx.commit().await?;
 
     Ok(Json(order))
 }
@@ -331,118 +519,65 @@ pub async fn cancel_order(
 pub async fn get_trades(
     State(state): State<Arc<AppState>>,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<Json<Vec<Trade>>, StatusCode> {
-    let client = state.db.get_client();
-    let symbol = params.get("symbol");
+) -> Result<Json<Vec<Trade>>, AppError> {
+    let client = checkout(&state).await?;
+    let symbol = params
+        .get("symbol")
+        .map(|symbol| symbol.parse::<InstrumentId>())
+        .transpose()
+        .map_err(AppError::validation)?;
     let user_id = params.get("user_id").and_then(|id| id.parse::<i32>().ok());
 
-    let query_str = match (symbol, user_id) {
-        (Some(_), Some(_)) => 
-            "SELECT trade_id, symbol, price, quantity, buy_order_id, sell_order_id, buyer_user_id, seller_user_id, aggressor_side, timestamp FROM trades WHERE symbol = $1 AND (buyer_user_id = $2 OR seller_user_id = $2) ORDER BY timestamp DESC",
-        (Some(_), None) => 
-            "SELECT trade_id, symbol, price, quantity, buy_order_id, sell_order_id, buyer_user_id, seller_user_id, aggressor_side, timestamp FROM trades WHERE symbol = $1 ORDER BY timestamp DESC",
-        (None, Some(_)) => 
-            "SELECT trade_id, symbol, price, quantity, buy_order_id, sell_order_id, buyer_user_id, seller_user_id, aggressor_side, timestamp FROM trades WHERE buyer_user_id = $1 OR seller_user_id = $1 ORDER BY timestamp DESC",
-        (None, None) => 
-            "SELECT trade_id, symbol, price, quantity, buy_order_id, sell_order_id, buyer_user_id, seller_user_id, aggressor_side, timestamp FROM trades ORDER BY timestamp DESC LIMIT 100",
-    };
-
-    let rows = match (symbol, user_id) {
-        (Some(sym), Some(uid)) => client.query(query_str, &[&sym, &uid]).await,
-        (Some(sym), None) => client.query(query_str, &[&sym]).await,
-        (None, Some(uid)) => client.query(query_str, &[&uid]).await,
-        (None, None) => client.query(query_str, &[]).await,
-    }.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let trades: Vec<Trade> = rows
-        .iter()
-        .map(|row| {
-            Trade {
-                trade_id: row.get(0),
-                symbol: row.get(1),
-                price: row.get::<_, String>(2).parse().unwrap_or_default(),
-                quantity: row.get::<_, String>(3).parse().unwrap_or_default(),
-                buy_order_id: row.get(4),
-                sell_order_id: row.get(5),
-                buyer_user_id: row.get(6),
-                seller_user_id: row.get(7),
-                aggressor_side: match row.get::<_, String>(8).as_str() {
-                    "buy" => OrderSide::Buy,
-                    "sell" => OrderSide::Sell,
-                    _ => OrderSide::Buy,
-                },
-                timestamp: row.get(9),
-            }
-        })
-        .collect();
+    let trades = trades::find(&client, symbol.as_ref(), user_id).await?;
 
     Ok(Json(trades))
 }
 
+// A user's side of each of their trades with the fees they paid on it, newest first
+pub async fn get_user_trades(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<i32>,
+) -> Result<Json<UserTradeHistory>, AppError> {
+    let client = checkout(&state).await?;
+    require_user(&client, user_id).await?;
+
+    let trades = trades::user_history(&client, user_id).await?;
+
+    // Totals are notional, since quantities of different symbols do not add up
+    let total = |bought: bool| -> Decimal {
+        trades
+            .iter()
+            .filter(|trade| matches!(trade.side, OrderSide::Buy) == bought)
+            .map(|trade| trade.price * trade.quantity)
+            .sum()
+    };
+    let history = UserTradeHistory {
+        user_id,
+        total_bought: total(true),
+        total_sold: total(false),
+        total_fees: trades.iter().map(|trade| trade.fees).sum(),
+        trades,
+    };
+
+    Ok(Json(history))
+}
+
 // Order book endpoints
 pub async fn get_order_book(
     State(state): State<Arc<AppState>>,
-    Path(symbol): Path<String>,
+    Path(symbol): Path<InstrumentId>,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<Json<OrderBookSnapshot>, StatusCode> {
-    let client = state.db.get_client();
+) -> Result<Json<OrderBookSnapshot>, AppError> {
+    let client = checkout(&state).await?;
     let depth = params.get("depth").and_then(|d| d.parse::<usize>().ok()).unwrap_or(10);
 
-    // Get bids (buy orders)
-    let bid_rows = client
-        .query(
-            "SELECT price, total_quantity, order_count FROM order_book_entries 
-             WHERE symbol = $1 AND side = 'bid' ORDER BY price DESC LIMIT $2",
-            &[&symbol, &(depth as i64)],
-        )
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Get asks (sell orders)
-    let ask_rows = client
-        .query(
-            "SELECT price, total_quantity, order_count FROM order_book_entries 
-             WHERE symbol = $1 AND side = 'ask' ORDER BY price ASC LIMIT $2",
-            &[&symbol, &(depth as i64)],
-        )
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Get market data
-    let market_row = client
-        .query_opt(
-            "SELECT best_bid, best_ask, mid_price, last_trade_price FROM market_data WHERE symbol = $1",
-            &[&symbol],
-        )
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let bids = market_data::book_levels(&client, &symbol, "bid", depth).await?;
+    let asks = market_data::book_levels(&client, &symbol, "ask", depth).await?;
+    let market = market_data::find(&client, &symbol).await?;
 
-    let bids: Vec<QuoteLevel> = bid_rows
-        .iter()
-        .map(|row| QuoteLevel {
-            price: row.get::<_, String>(0).parse().unwrap_or_default(),
-            quantity: row.get::<_, String>(1).parse().unwrap_or_default(),
-            order_count: row.get::<_, i32>(2) as usize,
-        })
-        .collect();
-
-    let asks: Vec<QuoteLevel> = ask_rows
-        .iter()
-        .map(|row| QuoteLevel {
-            price: row.get::<_, String>(0).parse().unwrap_or_default(),
-            quantity: row.get::<_, String>(1).parse().unwrap_or_default(),
-            order_count: row.get::<_, i32>(2) as usize,
-        })
-        .collect();
-
-    let (best_bid, best_ask, mid_price) = if let Some(row) = market_row {
-        (
-            row.get::<_, Option<String>>(0).and_then(|p| p.parse().ok()),
-            row.get::<_, Option<String>>(1).and_then(|p| p.parse().ok()),
-            row.get::<_, Option<String>>(2).and_then(|p| p.parse().ok()),
-        )
-    } else {
-        (None, None, None)
+    let (best_bid, best_ask, mid_price) = match market {
+        Some(market) => (market.best_bid, market.best_ask, market.mid_price),
+        None => (None, None, None),
     };
 
     let spread = match (best_bid, best_ask) {
@@ -467,27 +602,166 @@ pub async fn get_order_book(
 // Market data endpoints
 pub async fn get_market_data(
     State(state): State<Arc<AppState>>,
-    Path(symbol): Path<String>,
-) -> Result<Json<MarketData>, StatusCode> {
-    let client = state.db.get_client();
-    
-    let row = client
-        .query_one(
-            "SELECT symbol, best_bid, best_ask, mid_price, last_trade_price, last_trade_time, updated_at FROM market_data WHERE symbol = $1",
-            &[&symbol],
-        )
+    Path(symbol): Path<InstrumentId>,
+) -> Result<Json<MarketData>, AppError> {
+    let client = checkout(&state).await?;
+    let market_data = market_data::find(&client, &symbol)
+        .await?
+        .ok_or_else(|| market_not_found(&symbol))?;
+
+    Ok(Json(market_data))
+}
+// Market administration endpoints
+pub async fn list_markets(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Instrument>>, AppError> {
+    let instruments = state.instruments.read()?;
+    Ok(Json(instruments.list()))
+}
+
+// Registers an instrument and opens its market
+pub async fn create_market(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateInstrumentRequest>,
+) -> Result<Json<Instrument>, AppError> {
+    instruments::validate_definition(&payload).map_err(AppError::validation)?;
+
+    let mut client = checkout(&state).await?;
+    let tx = client.transaction().await?;
+    let instrument = instruments::register(&tx, &payload)
+        .await?
+        .ok_or_else(|| AppError::Conflict(format!("{} is already registered", payload.symbol)))?;
+    market_data::insert_empty(&tx, &payload.symbol).await?;
+    tx.commit().await?;
+
+    state
+        .engine
+        .open_market(instrument.symbol.clone(), instrument.lot_size)
         .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
-
-    let market_data = MarketData {
-        symbol: row.get(0),
-        best_bid: row.get::<_, Option<String>>(1).and_then(|p| p.parse().ok()),
-        best_ask: row.get::<_, Option<String>>(2).and_then(|p| p.parse().ok()),
-        mid_price: row.get::<_, Option<String>>(3).and_then(|p| p.parse().ok()),
-        last_trade_price: row.get::<_, Option<String>>(4).and_then(|p| p.parse().ok()),
-        last_trade_time: row.get(5),
-        updated_at: row.get(6),
+        .map_err(AppError::engine)?;
+    state
+        .instruments
+        .write()?
+        .insert(instrument.clone());
+
+    Ok(Json(instrument))
+}
+
+// A halted market refuses new orders; resting orders stay on the book and can be cancelled
+pub async fn halt_market(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<InstrumentId>,
+) -> Result<Json<Instrument>, AppError> {
+    set_market_status(&state, &symbol, MarketStatus::Halted).await
+}
+
+pub async fn resume_market(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<InstrumentId>,
+) -> Result<Json<Instrument>, AppError> {
+    set_market_status(&state, &symbol, MarketStatus::Active).await
+}
+
+async fn set_market_status(state: &AppState, symbol: &InstrumentId, status: MarketStatus) -> Result<Json<Instrument>, AppError> {
+    let instrument = instruments::set_status(&checkout(state).await?, symbol, status)
+        .await?
+        .ok_or_else(|| market_not_found(symbol))?;
+
+    state
+        .instruments
+        .write()?
+        .insert(instrument.clone());
+
+    Ok(Json(instrument))
+}
+
+// Corrects a user's available balance, with the reason recorded in the ledger
+pub async fn create_adjustment(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<AdjustmentRequest>,
+) -> Result<Json<Balance>, AppError> {
+    if payload.amount.is_zero() {
+        return Err(AppError::validation("the amount must not be zero"));
+    }
+    let asset = balance_change_asset(&state, &payload.asset, payload.amount)?;
+    if payload.reason.trim().is_empty() {
+        return Err(AppError::validation("adjustments need a reason"));
+    }
+
+    let mut client = checkout(&state).await?;
+    let tx = client.transaction().await?;
+    require_user(&tx, payload.user_id).await?;
+    // A debit, like a withdrawal, can only take what is available
+    let balance = if payload.amount < Decimal::ZERO {
+        balances::withdraw(&tx, payload.user_id, &asset, -payload.amount)
+            .await?
+            .ok_or_else(|| AppError::InsufficientFunds(format!("less than {} {} is available", -payload.amount, asset)))?
+    } else {
+        balances::credit(&tx, payload.user_id, &asset, payload.amount).await?
     };
+    let postings = [
+        ledger::Posting::new(ledger::Account::User(payload.user_id), &asset, payload.amount),
+        ledger::Posting::new(ledger::Account::Adjustments, &asset, -payload.amount),
+    ];
+    ledger::post(&tx, LedgerEntryKind::Adjustment, None, Some(payload.reason.trim()), &postings).await?;
+    tx.commit().await?;
+
+    Ok(Json(balance))
+}
 
-    Ok(Json(market_data))
-}
\ No newline at end of file
+// Checks the balances against the ledger now rather than waiting for the periodic run
+pub async fn get_reconciliation(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ReconciliationReport>, AppError> {
+    let mut client = checkout(&state).await?;
+    let report = ledger::reconcile(&mut client).await?;
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn fill(maker_order_id: i32, taker_order_id: i32, size: Decimal) -> Fill {
+        Fill {
+            maker_order_id,
+            maker_user_id: maker_order_id,
+            taker_order_id,
+            taker_user_id: taker_order_id,
+            price: dec!(100),
+            size,
+            aggressor: BidOrAsk::Bid,
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn a_stop_trading_against_the_incoming_remainder_counts_towards_its_fill() {
+        let mut order = Order {
+            order_id: 1,
+            user_id: 1,
+            symbol: InstrumentId::spot("BTC", "USD"),
+            side: OrderSide::Buy,
+            order_type: OrderType::Limit,
+            quantity: dec!(2),
+            limit_price: Some(dec!(100)),
+            stop_price: None,
+            filled_quantity: Decimal::ZERO,
+            remaining_quantity: dec!(2),
+            status: OrderStatus::Pending,
+            reject_reason: None,
+            time_in_force: TimeInForce::GTC,
+            submission_time: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        // Order 1 takes half a lot from 5, rests, then stop 7 fires and sells into it
+        let fills = [fill(5, 1, dec!(0.5)), fill(1, 7, dec!(1.5))];
+        let triggered = [TriggeredStop { order_id: 7, resting: false }];
+
+        assert_eq!(settle_incoming(&mut order, &fills, &triggered), dec!(2));
+        assert_eq!(order.filled_quantity, dec!(2));
+        assert_eq!(order.remaining_quantity, dec!(0));
+        assert_eq!(order.status, OrderStatus::Filled);
+    }
+}
@@ -0,0 +1,269 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use crate::database::{instruments, GenericClient};
+use crate::models::{column_overflow, CreateInstrumentRequest, Instrument, InstrumentId, MarketStatus};
+
+// Prices are stored with this many decimal places, so no instrument can ask for more
+const MAX_PRICE_PRECISION: u32 = 8;
+const MAX_SYMBOL_LENGTH: usize = 20;
+
+// Why an order breaks the constraints of its instrument
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstrumentViolation {
+    MarketHalted,
+    PriceNotPositive,
+    PriceTooPrecise { price_precision: u32 },
+    PriceOffTick { tick_size: Decimal },
+    QuantityNotPositive,
+    QuantityOffLot { lot_size: Decimal },
+    QuantityBelowMinimum { min_quantity: Decimal },
+    QuantityAboveMaximum { max_quantity: Decimal },
+    NotionalBelowMinimum { min_notional: Decimal },
+}
+
+impl std::fmt::Display for InstrumentViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstrumentViolation::MarketHalted => write!(f, "the market is halted"),
+            InstrumentViolation::PriceNotPositive => write!(f, "prices must be positive"),
+            InstrumentViolation::PriceTooPrecise { price_precision } => {
+                write!(f, "prices have at most {} decimal places", price_precision)
+            }
+            InstrumentViolation::PriceOffTick { tick_size } => write!(f, "prices must be a multiple of {}", tick_size),
+            InstrumentViolation::QuantityNotPositive => write!(f, "the quantity must be positive"),
+            InstrumentViolation::QuantityOffLot { lot_size } => write!(f, "the quantity must be a multiple of {}", lot_size),
+            InstrumentViolation::QuantityBelowMinimum { min_quantity } => {
+                write!(f, "the quantity must be at least {}", min_quantity)
+            }
+            InstrumentViolation::QuantityAboveMaximum { max_quantity } => {
+                write!(f, "the quantity must be at most {}", max_quantity)
+            }
+            InstrumentViolation::NotionalBelowMinimum { min_notional } => {
+                write!(f, "the order must be worth at least {}", min_notional)
+            }
+        }
+    }
+}
+
+// Every instrument by symbol, loaded at startup and kept in step by the admin endpoints
+#[derive(Debug, Default)]
+pub struct InstrumentRegistry {
+    instruments: HashMap<InstrumentId, Instrument>,
+}
+
+impl InstrumentRegistry {
+    pub fn new(instruments: Vec<Instrument>) -> Self {
+        InstrumentRegistry {
+            instruments: instruments.into_iter().map(|instrument| (instrument.symbol.clone(), instrument)).collect(),
+        }
+    }
+
+    pub fn get(&self, symbol: &InstrumentId) -> Option<&Instrument> {
+        self.instruments.get(symbol)
+    }
+
+    pub fn contains(&self, symbol: &InstrumentId) -> bool {
+        self.instruments.contains_key(symbol)
+    }
+
+    // Whether any market trades the asset, as base or quote
+    pub fn trades_asset(&self, asset: &str) -> bool {
+        self.instruments.keys().any(|symbol| symbol.base() == asset || symbol.quote() == asset)
+    }
+
+    // Sorted by symbol
+    pub fn list(&self) -> Vec<Instrument> {
+        let mut instruments: Vec<Instrument> = self.instruments.values().cloned().collect();
+        instruments.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        instruments
+    }
+
+    // Adds an instrument, or replaces the one with the same symbol
+    pub fn insert(&mut self, instrument: Instrument) {
+        self.instruments.insert(instrument.symbol.clone(), instrument);
+    }
+}
+
+pub async fn load_registry(client: &impl GenericClient) -> Result<InstrumentRegistry, tokio_postgres::Error> {
+    Ok(InstrumentRegistry::new(instruments::all(client).await?))
+}
+
+// None if the symbol is already registered
+pub async fn register(client: &impl GenericClient, request: &CreateInstrumentRequest) -> Result<Option<Instrument>, tokio_postgres::Error> {
+    instruments::insert(client, request, price_precision(request)).await
+}
+
+// None if the symbol is not registered
+pub async fn set_status(
+    client: &impl GenericClient,
+    symbol: &InstrumentId,
+    status: MarketStatus,
+) -> Result<Option<Instrument>, tokio_postgres::Error> {
+    instruments::set_status(client, symbol, status).await
+}
+
+// Checks an order against its instrument. `prices` are the limit and stop prices the order
+// carries, and `notional` what it is worth at them; a market order priced against an empty
+// book is worth nothing yet and is not held to the minimum.
+pub fn validate_order(
+    instrument: &Instrument,
+    quantity: Decimal,
+    prices: &[Decimal],
+    notional: Decimal,
+) -> Result<(), InstrumentViolation> {
+    if instrument.status == MarketStatus::Halted {
+        return Err(InstrumentViolation::MarketHalted);
+    }
+
+    for price in prices {
+        if *price <= Decimal::ZERO {
+            return Err(InstrumentViolation::PriceNotPositive);
+        }
+        if price.normalize().scale() > instrument.price_precision {
+            return Err(InstrumentViolation::PriceTooPrecise { price_precision: instrument.price_precision });
+        }
+        if !(price % instrument.tick_size).is_zero() {
+            return Err(InstrumentViolation::PriceOffTick { tick_size: instrument.tick_size });
+        }
+    }
+
+    if quantity <= Decimal::ZERO {
+        return Err(InstrumentViolation::QuantityNotPositive);
+    }
+    if !(quantity % instrument.lot_size).is_zero() {
+        return Err(InstrumentViolation::QuantityOffLot { lot_size: instrument.lot_size });
+    }
+    if quantity < instrument.min_quantity {
+        return Err(InstrumentViolation::QuantityBelowMinimum { min_quantity: instrument.min_quantity });
+    }
+    if let Some(max_quantity) = instrument.max_quantity.filter(|max| quantity > *max) {
+        return Err(InstrumentViolation::QuantityAboveMaximum { max_quantity });
+    }
+    if !notional.is_zero() && notional < instrument.min_notional {
+        return Err(InstrumentViolation::NotionalBelowMinimum { min_notional: instrument.min_notional });
+    }
+    Ok(())
+}
+
+// A new instrument has to be usable: positive increments, a price precision fine enough
+// for its tick size, and quantity bounds that leave something to trade. Every amount has to
+// fit its column exactly, since Postgres would round a finer one to a different increment.
+pub fn validate_definition(request: &CreateInstrumentRequest) -> Result<(), String> {
+    if request.symbol.to_string().len() > MAX_SYMBOL_LENGTH {
+        return Err(format!("symbols are at most {} characters", MAX_SYMBOL_LENGTH));
+    }
+    if request.tick_size <= Decimal::ZERO || request.lot_size <= Decimal::ZERO {
+        return Err("tick and lot sizes must be positive".to_string());
+    }
+    let amounts = [
+        ("tick_size", Some(request.tick_size)),
+        ("lot_size", Some(request.lot_size)),
+        ("min_quantity", request.min_quantity),
+        ("max_quantity", request.max_quantity),
+        ("min_notional", request.min_notional),
+    ];
+    for (field, amount) in amounts {
+        if let Some(message) = amount.and_then(column_overflow) {
+            return Err(format!("{} {}", field, message));
+        }
+    }
+
+    let price_precision = price_precision(request);
+    if price_precision > MAX_PRICE_PRECISION {
+        return Err(format!("prices have at most {} decimal places", MAX_PRICE_PRECISION));
+    }
+    if request.tick_size.normalize().scale() > price_precision {
+        return Err("the tick size has more decimal places than the price precision".to_string());
+    }
+
+    let min_quantity = request.min_quantity.unwrap_or_default();
+    if min_quantity < Decimal::ZERO || request.min_notional.is_some_and(|notional| notional < Decimal::ZERO) {
+        return Err("minimums cannot be negative".to_string());
+    }
+    if request.max_quantity.is_some_and(|max| max < min_quantity.max(request.lot_size)) {
+        return Err("the maximum quantity is below the smallest order allowed".to_string());
+    }
+    Ok(())
+}
+
+// Without an explicit precision, prices get as many decimal places as the tick size has
+pub fn price_precision(request: &CreateInstrumentRequest) -> u32 {
+    request.price_precision.unwrap_or(request.tick_size.normalize().scale())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn orders_must_fit_their_instrument() {
+        let mut btc = Instrument {
+            symbol: InstrumentId::spot("BTC", "USD"),
+            tick_size: dec!(0.05),
+            lot_size: dec!(0.001),
+            min_quantity: dec!(0.01),
+            max_quantity: Some(dec!(10)),
+            min_notional: dec!(10),
+            price_precision: 2,
+            status: MarketStatus::Active,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        assert_eq!(validate_order(&btc, dec!(0.5), &[dec!(100.05)], dec!(50.025)), Ok(()));
+        assert_eq!(validate_order(&btc, dec!(0.5), &[], dec!(0)), Ok(()));
+        assert_eq!(
+            validate_order(&btc, dec!(0.5), &[dec!(100.03)], dec!(50.015)),
+            Err(InstrumentViolation::PriceOffTick { tick_size: dec!(0.05) })
+        );
+        assert_eq!(
+            validate_order(&btc, dec!(0.5), &[dec!(100.001)], dec!(50)),
+            Err(InstrumentViolation::PriceTooPrecise { price_precision: 2 })
+        );
+        assert_eq!(
+            validate_order(&btc, dec!(0.0105), &[dec!(1000)], dec!(10.5)),
+            Err(InstrumentViolation::QuantityOffLot { lot_size: dec!(0.001) })
+        );
+        assert_eq!(
+            validate_order(&btc, dec!(0.005), &[dec!(1000)], dec!(5)),
+            Err(InstrumentViolation::QuantityBelowMinimum { min_quantity: dec!(0.01) })
+        );
+        assert_eq!(
+            validate_order(&btc, dec!(11), &[], dec!(0)),
+            Err(InstrumentViolation::QuantityAboveMaximum { max_quantity: dec!(10) })
+        );
+        assert_eq!(
+            validate_order(&btc, dec!(0.05), &[dec!(100)], dec!(5)),
+            Err(InstrumentViolation::NotionalBelowMinimum { min_notional: dec!(10) })
+        );
+
+        btc.status = MarketStatus::Halted;
+        assert_eq!(validate_order(&btc, dec!(0.5), &[dec!(100)], dec!(50)), Err(InstrumentViolation::MarketHalted));
+    }
+
+    #[test]
+    fn definitions_must_fit_their_columns() {
+        let request = |tick_size, lot_size| CreateInstrumentRequest {
+            symbol: InstrumentId::spot("ETH", "USD"),
+            tick_size,
+            lot_size,
+            min_quantity: None,
+            max_quantity: None,
+            min_notional: None,
+            price_precision: None,
+        };
+
+        assert_eq!(validate_definition(&request(dec!(0.01), dec!(0.00000001))), Ok(()));
+        assert_eq!(validate_definition(&request(dec!(-0.01), dec!(1))), Err("tick and lot sizes must be positive".to_string()));
+        assert_eq!(
+            validate_definition(&request(dec!(0.01), dec!(0.000000001))),
+            Err("lot_size must have at most 8 decimal places".to_string())
+        );
+        assert_eq!(
+            validate_definition(&request(dec!(10000000000), dec!(1))),
+            Err("tick_size must have at most 10 digits before the decimal point".to_string())
+        );
+    }
+}
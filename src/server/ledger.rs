@@ -0,0 +1,198 @@
+use chrono::Utc;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_postgres::IsolationLevel;
+use super::AppState;
+use super::error::AppError;
+use crate::database::{ledger, Client, GenericClient, Transaction};
+use crate::models::{InstrumentId, LedgerEntryKind, ReconciliationReport, StatementLine};
+
+// The ledger is checked against the balances this often unless RECONCILIATION_INTERVAL_SECS says otherwise
+const DEFAULT_RECONCILIATION_INTERVAL_SECS: u64 = 3600;
+
+// Who a posting is for. Every user has one account per asset holding both their available
+// and locked balance; the other accounts are the exchange's side of deposits, withdrawals,
+// fees and adjustments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Account {
+    User(i32),
+    External,
+    Fees,
+    Adjustments,
+}
+
+impl Account {
+    fn name(&self) -> &'static str {
+        match self {
+            Account::User(_) => "user",
+            Account::External => "external",
+            Account::Fees => "fees",
+            Account::Adjustments => "adjustments",
+        }
+    }
+
+    fn user_id(&self) -> Option<i32> {
+        match self {
+            Account::User(user_id) => Some(*user_id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub account: Account,
+    pub asset: String,
+    pub amount: Decimal,
+}
+
+impl Posting {
+    pub fn new(account: Account, asset: &str, amount: Decimal) -> Self {
+        Posting { account, asset: asset.to_string(), amount }
+    }
+}
+
+// An entry is balanced when its postings sum to zero in every asset
+pub fn unbalanced_asset(postings: &[Posting]) -> Option<&str> {
+    let mut totals: HashMap<&str, Decimal> = HashMap::new();
+    for posting in postings {
+        *totals.entry(&posting.asset).or_default() += posting.amount;
+    }
+    totals.into_iter().find(|(_, total)| !total.is_zero()).map(|(asset, _)| asset)
+}
+
+// The buyer pays the quote asset to the seller, who delivers the base asset
+pub fn trade_postings(symbol: &InstrumentId, buyer: i32, seller: i32, price: Decimal, size: Decimal) -> Vec<Posting> {
+    let notional = price * size;
+    vec![
+        Posting::new(Account::User(buyer), symbol.quote(), -notional),
+        Posting::new(Account::User(seller), symbol.quote(), notional),
+        Posting::new(Account::User(seller), symbol.base(), -size),
+        Posting::new(Account::User(buyer), symbol.base(), size),
+    ]
+}
+
+// Records an entry and its postings. Entries are never updated or deleted; a mistake is
+// corrected by another entry. An unbalanced entry is refused as an internal error, so the
+// transaction it was part of is rolled back.
+pub async fn post(
+    tx: &Transaction<'_>,
+    kind: LedgerEntryKind,
+    reference_id: Option<i32>,
+    description: Option<&str>,
+    postings: &[Posting],
+) -> Result<i32, AppError> {
+    if let Some(asset) = unbalanced_asset(postings) {
+        return Err(AppError::Internal(format!("unbalanced {} ledger entry in {}", kind, asset)));
+    }
+
+    let entry_id = ledger::insert_entry(tx, kind, reference_id, description).await?;
+    for posting in postings.iter().filter(|posting| !posting.amount.is_zero()) {
+        let account = &posting.account;
+        ledger::insert_posting(tx, entry_id, account.name(), account.user_id(), &posting.asset, posting.amount).await?;
+    }
+    Ok(entry_id)
+}
+
+// Deposits come in from outside the exchange and withdrawals go back out
+pub async fn post_transfer(
+    tx: &Transaction<'_>,
+    kind: LedgerEntryKind,
+    user_id: i32,
+    asset: &str,
+    amount: Decimal,
+) -> Result<i32, AppError> {
+    let amount = if kind == LedgerEntryKind::Withdrawal { -amount } else { amount };
+    post(
+        tx,
+        kind,
+        None,
+        None,
+        &[Posting::new(Account::User(user_id), asset, amount), Posting::new(Account::External, asset, -amount)],
+    )
+    .await
+}
+
+// A user's postings, oldest first, with their running balance in each asset
+pub async fn statement(
+    client: &impl GenericClient,
+    user_id: i32,
+    asset: Option<&str>,
+) -> Result<Vec<StatementLine>, tokio_postgres::Error> {
+    ledger::statement(client, user_id, asset).await
+}
+
+// Compares every user's balances, available plus locked, with the sum of the postings to
+// their account, and checks that every entry is balanced. Both are read from one snapshot,
+// so a balance change committed half way through is seen whole or not at all.
+pub async fn reconcile(client: &mut Client) -> Result<ReconciliationReport, tokio_postgres::Error> {
+    let tx = client
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()
+        .await?;
+    let drifts = ledger::drifts(&tx).await?;
+    let unbalanced_entries = ledger::unbalanced_entries(&tx).await?;
+    tx.commit().await?;
+
+    Ok(ReconciliationReport { checked_at: Utc::now(), drifts, unbalanced_entries })
+}
+
+pub fn reconciliation_interval() -> Duration {
+    let secs = env::var("RECONCILIATION_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_RECONCILIATION_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+pub async fn run_reconciliation(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let mut client = match state.db.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("failed to reconcile the ledger: {}", e);
+                continue;
+            }
+        };
+        match reconcile(&mut client).await {
+            Ok(report) => {
+                for drift in &report.drifts {
+                    eprintln!(
+                        "Ledger drift: user {} holds {} {} but the ledger says {}",
+                        drift.user_id, drift.balance, drift.asset, drift.ledger
+                    );
+                }
+                if !report.unbalanced_entries.is_empty() {
+                    eprintln!("Unbalanced ledger entries: {:?}", report.unbalanced_entries);
+                }
+            }
+            Err(e) => eprintln!("failed to reconcile the ledger: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn trades_balance_in_both_assets() {
+        let postings = trade_postings(&InstrumentId::spot("BTC", "USD"), 1, 2, dec!(100), dec!(0.5));
+        assert_eq!(unbalanced_asset(&postings), None);
+        assert!(postings.contains(&Posting::new(Account::User(1), "USD", dec!(-50))));
+        assert!(postings.contains(&Posting::new(Account::User(1), "BTC", dec!(0.5))));
+
+        let mut lopsided = postings;
+        lopsided.push(Posting::new(Account::Fees, "USD", dec!(0.05)));
+        assert_eq!(unbalanced_asset(&lopsided), Some("USD"));
+    }
+}
@@ -1,25 +1,71 @@
 pub mod routes;
 pub mod handlers;
+pub mod session;
+pub mod feed;
+pub mod fees;
+pub mod risk;
+pub mod settlement;
+pub mod valuation;
+pub mod persistence;
+pub mod instruments;
+pub mod balances;
+pub mod ledger;
+pub mod error;
 
 use axum::{Router, serve};
 use tower_http::cors::CorsLayer;
 use tokio::net::TcpListener;
-use std::sync::Arc;
-use crate::database::DatabaseConnection;
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use crate::database::Database;
+use crate::matching_engine::sequencer::EngineHandle;
+use crate::matching_engine::journal::Journal;
+use instruments::InstrumentRegistry;
 
 pub struct AppState {
-    pub db: Arc<DatabaseConnection>,
+    pub db: Database,
+    pub engine: EngineHandle,
+    pub instruments: RwLock<InstrumentRegistry>,
+    // Held shared from recording an order until what matching it produced is committed.
+    // Whatever frees the locks of orders the engine has taken off the book holds it
+    // exclusively, so fills matched before are written first.
+    pub settling: Arc<tokio::sync::RwLock<()>>,
 }
 
-pub async fn create_app(db: DatabaseConnection) -> Router {
+// Each run journals the engine's commands to a new file in this directory, where engine
+// snapshots are written too, unless JOURNAL_DIR says otherwise
+const DEFAULT_JOURNAL_DIR: &str = "journal";
+
+// The engine is recovered before any request is served, from its latest snapshot when
+// there is one and otherwise from the open orders in the database
+pub async fn create_app(db: Database) -> Result<Router, Box<dyn std::error::Error>> {
+    let journal_dir = PathBuf::from(env::var("JOURNAL_DIR").unwrap_or_else(|_| DEFAULT_JOURNAL_DIR.to_string()));
+    let journal = Journal::create(&journal_dir)?;
+    println!("Journaling engine commands to {}", journal.path().display());
+
+    let mut client = db.get().await?;
+    let instruments = instruments::load_registry(&client).await?;
+    let (engine, unmatched) = persistence::recover_engine(&mut client, &journal_dir, journal, &instruments).await?;
+    drop(client);
     let state = Arc::new(AppState {
-        db: Arc::new(db),
+        db,
+        engine: EngineHandle::spawn(engine)?,
+        instruments: RwLock::new(instruments),
+        settling: Arc::default(),
     });
+    persistence::resubmit_unmatched(&state, unmatched).await;
+
+    tokio::spawn(session::run_session_close(state.clone(), session::session_close()));
+    tokio::spawn(valuation::run_valuation(state.clone(), valuation::valuation_interval()));
+    tokio::spawn(persistence::run_book_projection(state.clone()));
+    tokio::spawn(ledger::run_reconciliation(state.clone(), ledger::reconciliation_interval()));
+    tokio::spawn(persistence::run_snapshots(state.clone(), journal_dir, persistence::snapshot_interval()));
 
-    Router::new()
+    Ok(Router::new()
         .merge(routes::create_routes())
         .layer(CorsLayer::permissive())
-        .with_state(state)
+        .with_state(state))
 }
 
 pub async fn start_server(app: Router) -> Result<(), Box<dyn std::error::Error>> {
@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use super::{balances, handlers, AppState};
+use super::error::AppError;
+use super::instruments::InstrumentRegistry;
+use crate::database::{market_data, orders, trades, Client, GenericClient, PoolError};
+use crate::matching_engine::engine::{Execution, MarketEvent, MarketUpdate, MatchingEngine};
+use crate::matching_engine::journal::Journal;
+use crate::matching_engine::snapshot::{self, EngineSnapshot};
+use crate::matching_engine::orderbook::{BidOrAsk, Order as EngineOrder};
+use crate::models::{InstrumentId, Order, OrderSide, OrderStatus, OrderType};
+
+// The engine is snapshotted this often unless SNAPSHOT_INTERVAL_SECS says otherwise
+const DEFAULT_SNAPSHOT_INTERVAL_SECS: u64 = 300;
+// Older snapshots are deleted once this many newer ones have been written
+const SNAPSHOTS_KEPT: usize = 3;
+// How long a snapshot waits for matched orders to be recorded before it is skipped
+const SETTLED_WAIT: Duration = Duration::from_secs(5);
+
+pub fn snapshot_interval() -> Duration {
+    let secs = env::var("SNAPSHOT_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_SNAPSHOT_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+// Recovers from the latest snapshot in `journal_dir` and the journal written after it.
+// Without a usable snapshot the engine is rebuilt from the database instead. Either way
+// every registered instrument ends up with a market, and the recovered engine is
+// snapshotted before it runs. Also returns the orders that were recorded but never
+// reached the engine, for `resubmit_unmatched` once it is running; those it holds without a
+// recorded outcome are cancelled.
+pub async fn recover_engine(
+    client: &mut Client,
+    journal_dir: &Path,
+    journal: Journal,
+    instruments: &InstrumentRegistry,
+) -> Result<(MatchingEngine, Vec<Order>), Box<dyn std::error::Error>> {
+    let (mut engine, placed) = match recovered_state(journal_dir) {
+        Some((state, placed)) => {
+            let mut engine = MatchingEngine::with_journal(journal);
+            engine.restore_snapshot(&state)?;
+            open_markets(&mut engine, instruments);
+            (engine, placed)
+        }
+        None => (rebuild_engine(&*client, Some(journal), instruments).await?, Vec::new()),
+    };
+    record_unrecorded(client, placed).await?;
+
+    let mut unmatched = Vec::new();
+    for order in orders::find_open(&*client).await?.into_iter().filter(is_unmatched) {
+        if !engine.holds_order(&order.symbol, order.order_id) {
+            unmatched.push(order);
+            continue;
+        }
+        // On the book, but what matching it produced is in neither the journal tail nor the
+        // database, so nothing tells what it owes. It is taken off the book and cancelled
+        // with its funds released, rather than left pending with its lock held.
+        eprintln!("order {} was on the book with no recorded outcome; cancelling it", order.order_id);
+        engine.cancel_order(order.symbol.clone(), order.order_id)?;
+        cancel_order_row(client, &order).await?;
+    }
+    checkpoint(journal_dir, &engine)?;
+    Ok((engine, unmatched))
+}
+
+// The engine state the latest usable snapshot and the journal after it add up to, and what
+// matching each order placed in that journal produced
+fn recovered_state(journal_dir: &Path) -> Option<(EngineSnapshot, Vec<(i32, Execution)>)> {
+    let (path, snapshot) = snapshot::latest_snapshot(journal_dir)?;
+    match snapshot::recover_state(journal_dir, &snapshot) {
+        Ok((state, report)) => {
+            println!("Recovered from {} and {} journaled commands after it", path.display(), report.commands);
+            if !report.is_identical() {
+                eprintln!("Journal replay differed from the recorded outcomes of commands {:?}", report.mismatches);
+            }
+            Some((state, report.placed))
+        }
+        Err(e) => {
+            eprintln!("failed to recover from {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+// Writes the outcomes of matches the journal holds but the database does not, such as those
+// of a run that stopped before it could record them. No snapshot is taken while an outcome
+// is unwritten, so every such match is in the journal after the latest one.
+async fn record_unrecorded(client: &mut Client, placed: Vec<(i32, Execution)>) -> Result<(), AppError> {
+    for (order_id, execution) in placed {
+        let Some(order) = orders::find_by_id(&*client, order_id).await? else {
+            eprintln!("order {} was matched but has no row to record it in", order_id);
+            continue;
+        };
+        let recorded = match execution.fills.first() {
+            Some(fill) => trades::exists(&*client, fill).await?,
+            // Nothing to write for a stop that is still waiting for its trigger
+            None => order.status != OrderStatus::Pending || (order.order_type.is_stop() && execution.triggered.is_empty()),
+        };
+        if !recorded {
+            handlers::record_execution(client, &order, &execution).await?;
+            println!("Recorded the outcome of order {} from the journal", order_id);
+        }
+    }
+    Ok(())
+}
+
+// Recovery only replays the journal a snapshot points at, so a run that died before its
+// first scheduled snapshot would be lost by the next one. A snapshot pointing at the new
+// journal is written before anything is journaled to it.
+fn checkpoint(journal_dir: &Path, engine: &MatchingEngine) -> std::io::Result<()> {
+    let path = write_snapshot(journal_dir, &engine.take_snapshot())?;
+    println!("Wrote engine snapshot {}", path.display());
+    Ok(())
+}
+
+// A limit or market order that was recorded and funded, but stopped short of being matched
+fn is_unmatched(order: &Order) -> bool {
+    order.status == OrderStatus::Pending && !order.order_type.is_stop()
+}
+
+// Matches the limit orders a restart caught between being recorded and being matched, as
+// if they had just been submitted. Market orders were priced against a book that has
+// since moved, so they are cancelled and their funds released instead.
+pub async fn resubmit_unmatched(state: &AppState, unmatched: Vec<Order>) {
+    for order in unmatched {
+        let order_id = order.order_id;
+        let result = match order.order_type {
+            OrderType::Limit => {
+                let settling = state.settling.clone().read_owned().await;
+                handlers::execute_order(state, order, None, settling).await.map(|_| ())
+            }
+            _ => cancel_unmatched(state, &order).await,
+        };
+        if let Err(e) = result {
+            eprintln!("failed to resubmit order {}: {:?}", order_id, e);
+        }
+    }
+}
+
+async fn cancel_unmatched(state: &AppState, order: &Order) -> Result<(), AppError> {
+    cancel_order_row(&mut state.db.get().await?, order).await
+}
+
+async fn cancel_order_row(client: &mut Client, order: &Order) -> Result<(), AppError> {
+    let tx = client.transaction().await?;
+    orders::cancel(&tx, order.order_id, order.user_id).await?;
+    balances::release_finished(&tx, &[order.order_id]).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// Opens a market with the instrument's lot size for every instrument that has none yet
+fn open_markets(engine: &mut MatchingEngine, instruments: &InstrumentRegistry) {
+    for instrument in instruments.list() {
+        if !engine.has_market(&instrument.symbol) {
+            engine.add_new_market_with_lot_size(instrument.symbol, instrument.lot_size);
+        }
+    }
+}
+
+// Builds the engine from the orders still open in the database. Orders are replayed in
+// submission order so each level gets back its time priority. Active orders rested on the
+// book, so they go back on it without matching, and pending stops go back to waiting for
+// their trigger. Pending limit and market orders never reached the engine; they are left
+// to `resubmit_unmatched`. With a journal, the restored state opens it, so the journal
+// alone replays into the same engine.
+pub async fn rebuild_engine(
+    client: &impl GenericClient,
+    journal: Option<Journal>,
+    instruments: &InstrumentRegistry,
+) -> Result<MatchingEngine, tokio_postgres::Error> {
+    let mut engine = match journal {
+        Some(journal) => MatchingEngine::with_journal(journal),
+        None => MatchingEngine::new(),
+    };
+    open_markets(&mut engine, instruments);
+
+    let mut restored = 0;
+    for open in orders::find_open(client).await? {
+        match restore_order(&mut engine, &open) {
+            Ok(true) => restored += 1,
+            Ok(false) => {}
+            Err(e) => eprintln!("failed to restore order {}: {}", open.order_id, e),
+        }
+    }
+
+    // Stops compare against the last trade price, so it has to survive the restart too
+    for (symbol, price) in market_data::last_trade_prices(client).await? {
+        if !engine.has_market(&symbol) {
+            engine.add_new_market(symbol.clone());
+        }
+        let _ = engine.restore_last_trade_price(&symbol, price);
+    }
+
+    println!("Restored {} open orders into {} markets", restored, engine.markets().count());
+    Ok(engine)
+}
+
+// Puts an open order back where it was in the engine, without matching it. False for an
+// order that was never in the engine.
+fn restore_order(engine: &mut MatchingEngine, open: &Order) -> Result<bool, String> {
+    let symbol = &open.symbol;
+    // Orders for a symbol that is no longer registered still get a market, so they can be cancelled
+    if !engine.has_market(symbol) {
+        engine.add_new_market(symbol.clone());
+    }
+    let mut order = EngineOrder::with_id(open.order_id, open.user_id, BidOrAsk::from(&open.side), open.remaining_quantity)
+        .with_time_in_force(open.time_in_force);
+    // A stop buy locked its quantity at the stop price and may spend no more once triggered
+    if let (OrderSide::Buy, OrderType::Stop, Some(stop_price)) = (&open.side, open.order_type, open.stop_price) {
+        order = order.with_budget(open.remaining_quantity * stop_price);
+    }
+
+    match (&open.status, open.order_type, open.limit_price, open.stop_price) {
+        // Limit orders and triggered stop-limits resting on the book
+        (OrderStatus::Active, OrderType::Limit | OrderType::StopLimit, Some(price), _) => {
+            engine.restore_limit_order(symbol, price, order)?
+        }
+        (OrderStatus::Pending, OrderType::Stop, _, Some(stop_price)) => engine.restore_stop_order(symbol, stop_price, None, order)?,
+        (OrderStatus::Pending, OrderType::StopLimit, Some(price), Some(stop_price)) => {
+            engine.restore_stop_order(symbol, stop_price, Some(price), order)?
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// The engine thread only copies the books; serializing and writing happen on a blocking thread.
+// A snapshot is only taken with no match waiting for its outcome to be written, since
+// recovery only records outcomes it finds in the journal after the snapshot.
+pub async fn run_snapshots(state: Arc<AppState>, journal_dir: PathBuf, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately, and there is nothing to save yet
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let Ok(settled) = tokio::time::timeout(SETTLED_WAIT, state.settling.write()).await else {
+            eprintln!("skipped an engine snapshot: matched orders are still being recorded");
+            continue;
+        };
+        let Ok(snapshot) = state.engine.take_snapshot().await else {
+            return;
+        };
+        drop(settled);
+        let dir = journal_dir.clone();
+        let written = tokio::task::spawn_blocking(move || write_snapshot(&dir, &snapshot)).await;
+        match written {
+            Ok(Ok(path)) => println!("Wrote engine snapshot {}", path.display()),
+            Ok(Err(e)) => eprintln!("failed to write an engine snapshot: {}", e),
+            Err(e) => eprintln!("failed to write an engine snapshot: {}", e),
+        }
+    }
+}
+
+fn write_snapshot(dir: &Path, snapshot: &EngineSnapshot) -> std::io::Result<PathBuf> {
+    let path = snapshot.write(dir)?;
+    snapshot::prune_snapshots(dir, SNAPSHOTS_KEPT)?;
+    Ok(path)
+}
+
+// Keeps `order_book_entries` and `market_data` in step with the engine by applying its
+// market data events. Each book is first written out in full from a snapshot, and again
+// whenever events were missed.
+pub async fn run_book_projection(state: Arc<AppState>) {
+    let mut events = state.engine.subscribe_market_data();
+    let mut applied: HashMap<InstrumentId, u64> = HashMap::new();
+    if let Err(e) = write_all_books(&state, &mut applied).await {
+        eprintln!("failed to write the order books: {}", e);
+    }
+
+    loop {
+        let result = match events.recv().await {
+            Ok(event) => apply_event(&state, &mut applied, event).await,
+            Err(RecvError::Lagged(_)) => write_all_books(&state, &mut applied).await,
+            Err(RecvError::Closed) => return,
+        };
+        if let Err(e) = result {
+            eprintln!("failed to update the stored order book: {}", e);
+        }
+    }
+}
+
+async fn write_all_books(state: &AppState, applied: &mut HashMap<InstrumentId, u64>) -> Result<(), PoolError> {
+    let Ok(snapshots) = state.engine.snapshots(usize::MAX).await else {
+        return Ok(());
+    };
+
+    let mut client = state.db.get().await?;
+    let tx = client.transaction().await?;
+    for (_, _, snapshot) in &snapshots {
+        market_data::clear_book(&tx, &snapshot.symbol).await?;
+        market_data::write_levels(&tx, &snapshot.symbol, "bid", &snapshot.bids).await?;
+        market_data::write_levels(&tx, &snapshot.symbol, "ask", &snapshot.asks).await?;
+        market_data::refresh_best_prices(&tx, &snapshot.symbol).await?;
+    }
+    tx.commit().await?;
+
+    for (symbol, sequence, _) in snapshots {
+        applied.insert(symbol, sequence);
+    }
+    Ok(())
+}
+
+// Events at or before the sequence a book was written out at are already reflected in it
+async fn apply_event(
+    state: &AppState,
+    applied: &mut HashMap<InstrumentId, u64>,
+    event: MarketEvent,
+) -> Result<(), PoolError> {
+    let last = applied.entry(event.instrument.clone()).or_insert(0);
+    if event.sequence <= *last {
+        return Ok(());
+    }
+    *last = event.sequence;
+
+    let symbol = &event.instrument;
+    let mut client = state.db.get().await?;
+    let tx = client.transaction().await?;
+    match event.update {
+        MarketUpdate::Levels { bids, asks } => {
+            market_data::write_levels(&tx, symbol, "bid", &bids).await?;
+            market_data::write_levels(&tx, symbol, "ask", &asks).await?;
+            market_data::refresh_best_prices(&tx, symbol).await?;
+        }
+        MarketUpdate::Ticker { price, timestamp } => {
+            market_data::record_last_trade(&tx, symbol, price, timestamp).await?;
+        }
+        // Trades are recorded by the request that produced them
+        MarketUpdate::Trade(_) => {}
+    }
+    Ok(tx.commit().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use crate::models::TimeInForce;
+
+    fn open_order(order_id: i32, side: OrderSide, order_type: OrderType, status: OrderStatus, price: Decimal) -> Order {
+        Order {
+            order_id,
+            user_id: order_id,
+            symbol: InstrumentId::spot("BTC", "USD"),
+            side,
+            order_type,
+            quantity: dec!(1),
+            limit_price: (order_type != OrderType::Stop).then_some(price),
+            stop_price: order_type.is_stop().then_some(price),
+            filled_quantity: Decimal::ZERO,
+            remaining_quantity: dec!(1),
+            status,
+            reject_reason: None,
+            time_in_force: TimeInForce::GTC,
+            submission_time: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn only_orders_that_were_in_the_engine_are_restored() {
+        let mut engine = MatchingEngine::new();
+        let symbol = InstrumentId::spot("BTC", "USD");
+
+        let resting = open_order(1, OrderSide::Sell, OrderType::Limit, OrderStatus::Active, dec!(100));
+        let stop = open_order(2, OrderSide::Buy, OrderType::Stop, OrderStatus::Pending, dec!(105));
+        // Would cross the resting ask; it has to be matched, not rested
+        let unmatched_limit = open_order(3, OrderSide::Buy, OrderType::Limit, OrderStatus::Pending, dec!(101));
+        let unmatched_market = open_order(4, OrderSide::Buy, OrderType::Market, OrderStatus::Pending, dec!(0));
+
+        assert_eq!(restore_order(&mut engine, &resting), Ok(true));
+        assert_eq!(restore_order(&mut engine, &stop), Ok(true));
+        assert_eq!(restore_order(&mut engine, &unmatched_limit), Ok(false));
+        assert_eq!(restore_order(&mut engine, &unmatched_market), Ok(false));
+
+        assert!(engine.holds_order(&symbol, 1) && engine.holds_order(&symbol, 2));
+        assert!(!engine.holds_order(&symbol, 3));
+        let (_, book) = engine.snapshot(&symbol, 10).unwrap();
+        assert!(book.bids.is_empty());
+        assert!(is_unmatched(&unmatched_limit) && is_unmatched(&unmatched_market) && !is_unmatched(&stop));
+    }
+
+    #[test]
+    fn restarting_twice_keeps_what_every_run_did() {
+        let dir = std::env::temp_dir().join(format!("persistence-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let symbol = InstrumentId::spot("BTC", "USD");
+        let ask = |id| EngineOrder::with_id(id, id, BidOrAsk::Ask, dec!(1));
+
+        let mut first = MatchingEngine::with_journal(Journal::create(&dir).unwrap());
+        first.add_new_market(symbol.clone());
+        first.place_limit_order(symbol.clone(), dec!(100), ask(1)).unwrap();
+        checkpoint(&dir, &first).unwrap();
+        first.place_limit_order(symbol.clone(), dec!(101), ask(2)).unwrap();
+        drop(first);
+
+        // Each restart dies before its first scheduled snapshot
+        let mut engine = None;
+        for id in 3..=4 {
+            let mut restarted = MatchingEngine::with_journal(Journal::create(&dir).unwrap());
+            restarted.restore_snapshot(&recovered_state(&dir).unwrap().0).unwrap();
+            checkpoint(&dir, &restarted).unwrap();
+            restarted.place_limit_order(symbol.clone(), dec!(100) + Decimal::from(id), ask(id)).unwrap();
+            engine = Some(restarted);
+        }
+        let engine = engine.unwrap();
+
+        let mut recovered = MatchingEngine::new();
+        let (state, placed) = recovered_state(&dir).unwrap();
+        recovered.restore_snapshot(&state).unwrap();
+        // Only what the last run placed has an outcome that may still need recording
+        assert_eq!(placed.iter().map(|(order_id, _)| *order_id).collect::<Vec<_>>(), vec![4]);
+        assert!((1..=4).all(|id| recovered.holds_order(&symbol, id)));
+        let expected = serde_json::to_string(&engine.take_snapshot().markets).unwrap();
+        assert_eq!(serde_json::to_string(&recovered.take_snapshot().markets).unwrap(), expected);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,86 @@
+use rust_decimal::Decimal;
+use crate::database::{balances, users, Transaction};
+use crate::models::{InstrumentId, OrderSide, RejectReason};
+
+// What a user can still commit to new orders in a market: the available balances of its
+// quote and base assets. What open orders hold is already locked and not counted.
+#[derive(Debug, Clone, Default)]
+pub struct Exposure {
+    pub quote_available: Decimal,
+    pub base_available: Decimal,
+}
+
+// Buys need `notional` of the quote asset plus the most they can be charged in fees,
+// sells `quantity` of the base asset
+pub fn check_order(
+    side: &OrderSide,
+    quantity: Decimal,
+    notional: Decimal,
+    max_fee: Decimal,
+    exposure: &Exposure,
+) -> Result<(), RejectReason> {
+    match side {
+        OrderSide::Buy if notional + max_fee > exposure.quote_available => Err(RejectReason::InsufficientFunds),
+        OrderSide::Sell if quantity > exposure.base_available => Err(RejectReason::InsufficientPosition),
+        _ => Ok(()),
+    }
+}
+
+// What an accepted order locks, in the asset `check_order` held it against
+pub fn lock_amount(side: &OrderSide, quantity: Decimal, notional: Decimal, max_fee: Decimal) -> Decimal {
+    match side {
+        OrderSide::Buy => notional + max_fee,
+        OrderSide::Sell => quantity,
+    }
+}
+
+// None if the user does not exist. Read in the transaction that records and locks the
+// order, so that no other order can spend the same balance in between.
+pub async fn load_exposure(
+    tx: &Transaction<'_>,
+    user_id: i32,
+    symbol: &InstrumentId,
+) -> Result<Option<Exposure>, tokio_postgres::Error> {
+    if !users::exists(tx, user_id).await? {
+        return Ok(None);
+    }
+
+    let assets = [symbol.quote(), symbol.base()];
+    let mut exposure = Exposure::default();
+    for (asset, available) in balances::available_for_update(tx, user_id, &assets).await? {
+        if asset == symbol.quote() {
+            exposure.quote_available = available;
+        } else {
+            exposure.base_available = available;
+        }
+    }
+    Ok(Some(exposure))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn orders_must_fit_in_the_available_balances() {
+        let exposure = Exposure { quote_available: dec!(600), base_available: dec!(3) };
+
+        assert!(check_order(&OrderSide::Buy, dec!(6), dec!(600), dec!(0), &exposure).is_ok());
+        assert_eq!(
+            check_order(&OrderSide::Buy, dec!(6), dec!(600.01), dec!(0), &exposure),
+            Err(RejectReason::InsufficientFunds)
+        );
+        // The fee has to fit as well
+        assert_eq!(
+            check_order(&OrderSide::Buy, dec!(6), dec!(599), dec!(1.2), &exposure),
+            Err(RejectReason::InsufficientFunds)
+        );
+        assert_eq!(lock_amount(&OrderSide::Buy, dec!(6), dec!(599), dec!(1)), dec!(600));
+        assert!(check_order(&OrderSide::Sell, dec!(3), dec!(0), dec!(0), &exposure).is_ok());
+        assert_eq!(
+            check_order(&OrderSide::Sell, dec!(3.5), dec!(0), dec!(0), &exposure),
+            Err(RejectReason::InsufficientPosition)
+        );
+    }
+}
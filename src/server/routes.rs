@@ -1,9 +1,9 @@
 use axum::{
-    routing::{get, post, put, delete},
+    routing::{get, post},
     Router,
 };
 use std::sync::Arc;
-use super::{handlers, AppState};
+use super::{feed, handlers, AppState};
 
 pub fn create_routes() -> Router<Arc<AppState>> {
     Router::new()
@@ -14,6 +14,12 @@ pub fn create_routes() -> Router<Arc<AppState>> {
         .route("/users", post(handlers::create_user))
         .route("/users/:user_id", get(handlers::get_user))
         .route("/users/:user_id/profile", get(handlers::get_user_profile))
+        .route("/users/:user_id/positions", get(handlers::get_user_positions))
+        .route("/users/:user_id/trades", get(handlers::get_user_trades))
+        .route("/users/:user_id/balances", get(handlers::get_user_balances))
+        .route("/users/:user_id/deposits", post(handlers::deposit))
+        .route("/users/:user_id/withdrawals", post(handlers::withdraw))
+        .route("/users/:user_id/statement", get(handlers::get_user_statement))
         
         // Order management
         .route("/orders", post(handlers::create_order))
@@ -26,4 +32,17 @@ pub fn create_routes() -> Router<Arc<AppState>> {
         // Order book and market data
         .route("/orderbook/:symbol", get(handlers::get_order_book))
         .route("/market/:symbol", get(handlers::get_market_data))
+
+        // Streaming market data
+        .route("/ws/market", get(feed::market_data_ws))
+
+        // Market administration
+        .route("/admin/markets", get(handlers::list_markets))
+        .route("/admin/markets", post(handlers::create_market))
+        .route("/admin/markets/:symbol/halt", post(handlers::halt_market))
+        .route("/admin/markets/:symbol/resume", post(handlers::resume_market))
+
+        // Ledger administration
+        .route("/admin/adjustments", post(handlers::create_adjustment))
+        .route("/admin/reconciliation", get(handlers::get_reconciliation))
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use std::env;
+use std::sync::Arc;
+use super::{balances, AppState};
+use crate::database::{orders, PoolError};
+
+// DAY orders expire at this time of day (UTC) unless SESSION_CLOSE_UTC says otherwise
+const DEFAULT_SESSION_CLOSE: &str = "21:00";
+
+pub fn session_close() -> NaiveTime {
+    env::var("SESSION_CLOSE_UTC")
+        .ok()
+        .and_then(|close| NaiveTime::parse_from_str(&close, "%H:%M").ok())
+        .unwrap_or_else(|| NaiveTime::parse_from_str(DEFAULT_SESSION_CLOSE, "%H:%M").unwrap())
+}
+
+fn until_next_close(now: DateTime<Utc>, close: NaiveTime) -> std::time::Duration {
+    let today = now.date_naive().and_time(close).and_utc();
+    let next = if today > now { today } else { today + Duration::days(1) };
+    (next - now).to_std().unwrap_or_default()
+}
+
+pub async fn run_session_close(state: Arc<AppState>, close: NaiveTime) {
+    loop {
+        tokio::time::sleep(until_next_close(Utc::now(), close)).await;
+        if let Err(e) = expire_day_orders(&state).await {
+            eprintln!("failed to expire DAY orders: {}", e);
+        }
+    }
+}
+
+pub async fn expire_day_orders(state: &AppState) -> Result<u64, PoolError> {
+    let expired: Vec<i32> = state
+        .engine
+        .expire_day_orders()
+        .await
+        .map_or(Vec::new(), |expired| expired.iter().map(|(_, order)| order.id()).collect());
+
+    // Fills the expired orders made before the close are written before their locks are
+    // released, and no order is between being recorded and being matched meanwhile
+    let _settled = state.settling.write().await;
+    let mut client = state.db.get().await?;
+    let tx = client.transaction().await?;
+    let expired_ids = orders::expire_day_orders(&tx, &expired).await?;
+    balances::release_finished(&tx, &expired_ids).await?;
+    tx.commit().await?;
+    let updated = expired_ids.len() as u64;
+
+    println!("Session closed: {} DAY orders removed from the book, {} expired in the database", expired.len(), updated);
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn next_close_is_today_or_tomorrow() {
+        let close = NaiveTime::from_hms_opt(21, 0, 0).unwrap();
+
+        let morning = Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap();
+        assert_eq!(until_next_close(morning, close), std::time::Duration::from_secs(12 * 3600));
+
+        let at_close = Utc.with_ymd_and_hms(2024, 1, 1, 21, 0, 0).unwrap();
+        assert_eq!(until_next_close(at_close, close), std::time::Duration::from_secs(24 * 3600));
+    }
+}
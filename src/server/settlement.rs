@@ -0,0 +1,118 @@
+use rust_decimal::Decimal;
+use crate::database::{positions, users, Transaction};
+use super::{balances, ledger};
+use super::error::AppError;
+use crate::matching_engine::orderbook::Fill;
+use crate::models::{InstrumentId, LedgerEntryKind};
+
+// A user's holding in one symbol: signed quantity (negative when short) at an average cost
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Holding {
+    pub quantity: Decimal,
+    pub avg_cost: Decimal,
+}
+
+impl Holding {
+    // Applies a trade of `quantity` (positive bought, negative sold) at `price`. Trades that
+    // grow the holding move its average cost; trades that shrink it realize P&L against the
+    // average cost and leave it unchanged. A trade that flips the holding opens the new
+    // side at `price`. Returns the new holding and the realized P&L.
+    pub fn apply(self, quantity: Decimal, price: Decimal) -> (Holding, Decimal) {
+        let next_quantity = self.quantity + quantity;
+
+        if self.quantity.is_zero() || self.quantity.is_sign_positive() == quantity.is_sign_positive() {
+            let cost = self.quantity.abs() * self.avg_cost + quantity.abs() * price;
+            let avg_cost = if next_quantity.is_zero() { Decimal::ZERO } else { cost / next_quantity.abs() };
+            return (Holding { quantity: next_quantity, avg_cost }, Decimal::ZERO);
+        }
+
+        let closed = quantity.abs().min(self.quantity.abs());
+        let realized = if self.quantity.is_sign_positive() {
+            (price - self.avg_cost) * closed
+        } else {
+            (self.avg_cost - price) * closed
+        };
+        let avg_cost = if next_quantity.is_zero() {
+            Decimal::ZERO
+        } else if next_quantity.is_sign_positive() == self.quantity.is_sign_positive() {
+            self.avg_cost
+        } else {
+            price
+        };
+        (Holding { quantity: next_quantity, avg_cost }, realized)
+    }
+}
+
+// Moves assets between the buyer and the seller of a fill, posts the movement to the ledger
+// and updates their positions. Run it in the transaction that inserts the trade, so a trade
+// is never recorded without its settlement.
+pub async fn settle_fill(
+    tx: &Transaction<'_>,
+    trade_id: i32,
+    symbol: &InstrumentId,
+    fill: &Fill,
+) -> Result<(), AppError> {
+    // The buyer pays the quote asset out of what their order locked and receives the base
+    // asset; the seller delivers the base asset and receives the quote
+    let notional = fill.price * fill.size;
+    balances::spend(tx, fill.buy_order_id(), fill.buyer_user_id(), symbol.quote(), notional).await?;
+    balances::credit(tx, fill.buyer_user_id(), symbol.base(), fill.size).await?;
+    balances::spend(tx, fill.sell_order_id(), fill.seller_user_id(), symbol.base(), fill.size).await?;
+    balances::credit(tx, fill.seller_user_id(), symbol.quote(), notional).await?;
+    let postings = ledger::trade_postings(symbol, fill.buyer_user_id(), fill.seller_user_id(), fill.price, fill.size);
+    ledger::post(tx, LedgerEntryKind::Trade, Some(trade_id), None, &postings).await?;
+
+    settle_side(tx, fill.buyer_user_id(), symbol, fill.size, fill.price).await?;
+    settle_side(tx, fill.seller_user_id(), symbol, -fill.size, fill.price).await?;
+    Ok(())
+}
+
+async fn settle_side(
+    tx: &Transaction<'_>,
+    user_id: i32,
+    symbol: &InstrumentId,
+    quantity: Decimal,
+    price: Decimal,
+) -> Result<(), tokio_postgres::Error> {
+    let holding = positions::find_for_update(tx, user_id, symbol)
+        .await?
+        .map(|position| Holding { quantity: position.quantity, avg_cost: position.avg_cost })
+        .unwrap_or_default();
+
+    let (holding, realized_pnl) = holding.apply(quantity, price);
+
+    positions::save(tx, user_id, symbol, holding.quantity, holding.avg_cost).await?;
+    users::add_realized_pnl(tx, user_id, realized_pnl).await
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn buys_average_in_and_sells_realize_against_the_average() {
+        let holding = Holding::default();
+        let (holding, realized) = holding.apply(dec!(2), dec!(100));
+        assert_eq!((holding, realized), (Holding { quantity: dec!(2), avg_cost: dec!(100) }, dec!(0)));
+
+        let (holding, realized) = holding.apply(dec!(2), dec!(110));
+        assert_eq!((holding, realized), (Holding { quantity: dec!(4), avg_cost: dec!(105) }, dec!(0)));
+
+        let (holding, realized) = holding.apply(dec!(-1), dec!(120));
+        assert_eq!((holding, realized), (Holding { quantity: dec!(3), avg_cost: dec!(105) }, dec!(15)));
+
+        let (holding, realized) = holding.apply(dec!(-3), dec!(100));
+        assert_eq!((holding, realized), (Holding { quantity: dec!(0), avg_cost: dec!(0) }, dec!(-15)));
+    }
+
+    #[test]
+    fn a_trade_through_zero_opens_the_other_side_at_the_trade_price() {
+        let holding = Holding { quantity: dec!(1), avg_cost: dec!(100) };
+        let (holding, realized) = holding.apply(dec!(-3), dec!(90));
+        assert_eq!((holding, realized), (Holding { quantity: dec!(-2), avg_cost: dec!(90) }, dec!(-10)));
+
+        let (holding, realized) = holding.apply(dec!(1), dec!(80));
+        assert_eq!((holding, realized), (Holding { quantity: dec!(-1), avg_cost: dec!(90) }, dec!(10)));
+    }
+}
@@ -0,0 +1,100 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use super::AppState;
+use crate::database::positions::MarkedPosition;
+use crate::database::{positions, users, Client, GenericClient};
+use crate::models::{InstrumentId, PositionSummary};
+
+// Unrealized P&L totals are refreshed this often unless VALUATION_INTERVAL_SECS says otherwise
+const DEFAULT_VALUATION_INTERVAL_SECS: u64 = 10;
+
+pub fn valuation_interval() -> Duration {
+    let secs = env::var("VALUATION_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_VALUATION_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+// Without a mark the position can only be shown at cost
+pub fn mark_position(symbol: InstrumentId, quantity: Decimal, avg_cost: Decimal, mark: Option<Decimal>) -> PositionSummary {
+    let cost_basis = avg_cost * quantity.abs();
+    let unrealized_pnl = mark.map(|mark| (mark - avg_cost) * quantity);
+    PositionSummary {
+        symbol,
+        quantity,
+        avg_cost,
+        market_value: mark.map(|mark| mark * quantity),
+        unrealized_pnl,
+        unrealized_pnl_percent: unrealized_pnl
+            .filter(|_| !cost_basis.is_zero())
+            .map(|pnl| (pnl / cost_basis * Decimal::ONE_HUNDRED).round_dp(4)),
+    }
+}
+
+fn summary(position: MarkedPosition) -> PositionSummary {
+    mark_position(position.symbol, position.quantity, position.avg_cost, position.mark)
+}
+
+pub async fn position_summaries(client: &impl GenericClient, user_id: i32) -> Result<Vec<PositionSummary>, tokio_postgres::Error> {
+    Ok(positions::marked_for_user(client, user_id).await?.into_iter().map(summary).collect())
+}
+
+// Writes every user's total unrealized P&L in one transaction; users without open
+// positions go back to zero
+pub async fn refresh_unrealized_pnl(client: &mut Client) -> Result<usize, tokio_postgres::Error> {
+    let tx = client.transaction().await?;
+    let mut totals: HashMap<i32, Decimal> = HashMap::new();
+    for position in positions::all_marked(&tx).await? {
+        let user_id = position.user_id;
+        *totals.entry(user_id).or_default() += summary(position).unrealized_pnl.unwrap_or_default();
+    }
+
+    let totals: Vec<(i32, Decimal)> = totals.into_iter().collect();
+    users::set_unrealized_pnl(&tx, &totals).await?;
+    tx.commit().await?;
+    Ok(totals.len())
+}
+
+pub async fn run_valuation(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let mut client = match state.db.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("failed to refresh unrealized P&L: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = refresh_unrealized_pnl(&mut client).await {
+            eprintln!("failed to refresh unrealized P&L: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn positions_are_marked_against_their_cost() {
+        let long = mark_position(InstrumentId::spot("BTC", "USD"), dec!(2), dec!(100), Some(dec!(110)));
+        assert_eq!(long.market_value, Some(dec!(220)));
+        assert_eq!(long.unrealized_pnl, Some(dec!(20)));
+        assert_eq!(long.unrealized_pnl_percent, Some(dec!(10)));
+
+        let short = mark_position(InstrumentId::spot("BTC", "USD"), dec!(-2), dec!(100), Some(dec!(110)));
+        assert_eq!(short.unrealized_pnl, Some(dec!(-20)));
+        assert_eq!(short.unrealized_pnl_percent, Some(dec!(-10)));
+
+        let unmarked = mark_position(InstrumentId::spot("BTC", "USD"), dec!(2), dec!(100), None);
+        assert_eq!(unmarked.market_value, None);
+        assert_eq!(unmarked.unrealized_pnl_percent, None);
+    }
+}